@@ -1,15 +1,44 @@
+use crate::capabilities::{Capabilities, Capability};
 use crate::cgroup::CgroupOptions;
+use crate::jail::JailOptions;
+use crate::jaillogs::{parse_since, LogConfig, LogDriver, LogsOptions};
+use crate::monitor::{parse_detach_keys, DEFAULT_DETACH_KEYS};
+use crate::mount::{Tmpfs, Volume};
+use crate::network::{NetworkError, NetworkMode, PortMapping};
+use crate::seccomp::SeccompOption;
+use crate::state::{Filter, ListFormat, ListOptions};
+use crate::stats::StatsOptions;
+use crate::ulimit::Ulimit;
+use chrono::{DateTime, Utc};
+use nix::sys::signal::Signal;
 use std::convert::TryFrom;
+use std::fs::read_to_string;
 use std::str::FromStr;
+use std::time::Duration;
 
+const CAP_ADD_OPTION: &str = "--cap-add=";
+const CAP_DROP_OPTION: &str = "--cap-drop=";
 const CPU_MAX_OPTION: &str = "--cpu-max=";
 const CPU_WEIGHT_OPTION: &str = "--cpu-weight=";
 const CPU_WEIGHT_NICE_OPTION: &str = "--cpu-weight-nice=";
 const CPUSET_CPUS_OPTION: &str = "--cpuset-cpus=";
 const CPUSET_CPUS_PARTITION_OPTION: &str = "--cpuset-cpus-partition=";
 const CPUSET_MEMS_OPTION: &str = "--cpuset-mems=";
+const DETACH_KEYS_OPTION: &str = "--detach-keys=";
+const ENTRYPOINT_OPTION: &str = "--entrypoint=";
+const ENV_OPTION: &str = "--env=";
+const ENV_FILE_OPTION: &str = "--env-file=";
+const FILTER_OPTION: &str = "--filter=";
+const FORMAT_OPTION: &str = "--format=";
 const IO_MAX_OPTION: &str = "--io-max=";
 const IO_WEIGHT_OPTION: &str = "--io-weight=";
+const NETWORK_OPTION: &str = "--network=";
+const PUBLISH_OPTION: &str = "--publish=";
+const LOG_DRIVER_OPTION: &str = "--log-driver=";
+const LOG_OPT_OPTION: &str = "--log-opt=";
+const MAX_FILE_LOG_OPT: &str = "max-file=";
+const MAX_SIZE_LOG_OPT: &str = "max-size=";
+const MOUNT_OPTION: &str = "--mount=";
 const MEMORY_HIGH_OPTION: &str = "--memory-high=";
 const MEMORY_LOW_OPTION: &str = "--memory-low=";
 const MEMORY_MAX_OPTION: &str = "--memory-max=";
@@ -18,6 +47,21 @@ const MEMORY_OOM_GROUP_OPTION: &str = "--memory-oom-group=";
 const MEMORY_SWAP_MAX_OPTION: &str = "--memory-swap-max=";
 const PIDS_MAX_OPTION: &str = "--pids-max=";
 const RDMA_MAX_OPTION: &str = "--rdma-max=";
+const SECURITY_OPT_OPTION: &str = "--security-opt=";
+const SECCOMP_SECURITY_OPT: &str = "seccomp=";
+const SIGNAL_OPTION: &str = "--signal=";
+const SINCE_OPTION: &str = "--since=";
+const SHM_SIZE_OPTION: &str = "--shm-size=";
+const TAIL_OPTION: &str = "--tail=";
+const TIME_OPTION: &str = "--time=";
+const TMPFS_OPTION: &str = "--tmpfs=";
+const ULIMIT_OPTION: &str = "--ulimit=";
+const USER_OPTION: &str = "--user=";
+const VOLUME_OPTION: &str = "--volume=";
+const WORKDIR_OPTION: &str = "--workdir=";
+const DEFAULT_KILL_SIGNAL: Signal = Signal::SIGKILL;
+const DEFAULT_STOP_SIGNAL: Signal = Signal::SIGTERM;
+const DEFAULT_STOP_TIMEOUT: Duration = Duration::from_secs(10);
 
 #[derive(Debug, Fail)]
 pub(crate) enum ArgumentParsingError {
@@ -43,24 +87,136 @@ pub(crate) enum ArgumentParsingError {
     CantParseNumber(String),
     #[fail(display = "Invalid argument {}.", 0)]
     InvalidArgument(String),
+    #[fail(display = "Missing network mode.")]
+    MissingNetworkMode,
+    #[fail(display = "{}.", 0)]
+    InvalidNetwork(NetworkError),
+    #[fail(display = "Missing port mapping.")]
+    MissingPortMapping,
+    #[fail(display = "Publishing ports requires a network mode other than host.")]
+    PublishWithHostNetwork,
+    #[fail(display = "Invalid capability {}.", 0)]
+    InvalidCapability(String),
+    #[fail(display = "Missing security option.")]
+    MissingSecurityOption,
+    #[fail(display = "Invalid security option {}.", 0)]
+    InvalidSecurityOption(String),
+    #[fail(display = "Missing ulimit.")]
+    MissingUlimit,
+    #[fail(display = "Invalid ulimit {}: {}.", 0, 1)]
+    InvalidUlimit(String, String),
+    #[fail(display = "Missing environment variable.")]
+    MissingEnvironmentVariable,
+    #[fail(display = "Missing environment file.")]
+    MissingEnvironmentFile,
+    #[fail(display = "Can't read environment file {}: {}.", 0, 1)]
+    InvalidEnvironmentFile(String, String),
+    #[fail(display = "Missing working directory.")]
+    MissingWorkdir,
+    #[fail(display = "The working directory {} should be an absolute path.", 0)]
+    InvalidWorkdir(String),
+    #[fail(display = "Missing user.")]
+    MissingUser,
+    #[fail(display = "Missing volume.")]
+    MissingVolume,
+    #[fail(display = "Invalid volume {}: {}.", 0, 1)]
+    InvalidVolume(String, String),
+    #[fail(display = "Missing tmpfs.")]
+    MissingTmpfs,
+    #[fail(display = "Invalid tmpfs {}: {}.", 0, 1)]
+    InvalidTmpfs(String, String),
+    #[fail(display = "Missing shared memory size.")]
+    MissingShmSize,
+    #[fail(display = "Invalid size {}.", 0)]
+    InvalidSize(String),
+    #[fail(display = "Missing command to execute.")]
+    MissingExecCommand,
+    #[fail(display = "Invalid detach keys {}.", 0)]
+    InvalidDetachKeys(String),
+    #[fail(display = "Invalid time {}.", 0)]
+    InvalidSince(String),
+    #[fail(display = "Missing number of lines.")]
+    MissingTail,
+    #[fail(display = "Missing log driver.")]
+    MissingLogDriver,
+    #[fail(display = "Invalid log driver {}.", 0)]
+    InvalidLogDriver(String),
+    #[fail(display = "Missing log option.")]
+    MissingLogOption,
+    #[fail(display = "Invalid log option {}.", 0)]
+    InvalidLogOption(String),
+    #[fail(display = "Log options require the json-file log driver.")]
+    LogOptionWithoutJsonFile,
+    #[fail(display = "Missing filter.")]
+    MissingFilter,
+    #[fail(display = "Invalid filter {}.", 0)]
+    InvalidFilter(String),
+    #[fail(display = "Missing format.")]
+    MissingFormat,
+    #[fail(display = "Invalid format {}.", 0)]
+    InvalidFormat(String),
+    #[fail(display = "Missing signal.")]
+    MissingSignal,
+    #[fail(display = "Invalid signal {}.", 0)]
+    InvalidSignal(String),
+    #[fail(display = "Missing time.")]
+    MissingTime,
+    #[fail(display = "Missing resource option.")]
+    MissingResourceOption,
 }
 
 pub(crate) enum Command {
+    Attach {
+        container: String,
+        detach_keys: Vec<u8>,
+    },
     DeleteContainer(String),
     DeleteImage(String),
+    Exec {
+        container: String,
+        command: Vec<String>,
+        interactive: bool,
+        tty: bool,
+    },
     Export(String, String),
     Help(Option<String>),
     Import(String),
-    ListContainers,
+    KillContainer {
+        container: String,
+        signal: Signal,
+        all: bool,
+    },
+    ListContainers(ListOptions),
     ListImages,
-    Logs(String),
+    Logs {
+        container: String,
+        options: LogsOptions,
+    },
+    PauseContainer(String),
     Run {
         command: Vec<String>,
-        detach: bool,
+        entrypoint: Option<String>,
         image: String,
+        jail_options: Box<JailOptions>,
         name: Option<String>,
+        options: Vec<String>,
         resource_options: Vec<CgroupOptions>,
     },
+    Stats {
+        containers: Vec<String>,
+        options: StatsOptions,
+    },
+    StopContainer {
+        container: String,
+        signal: Signal,
+        time: Duration,
+    },
+    UnpauseContainer(String),
+    UpdateContainer {
+        container: String,
+        resource_options: Vec<CgroupOptions>,
+        resources: Vec<String>,
+    },
 }
 
 fn parse_container_subcommand<I: Iterator<Item = String>>(
@@ -70,16 +226,195 @@ fn parse_container_subcommand<I: Iterator<Item = String>>(
         .next()
         .ok_or(ArgumentParsingError::NoContainerSubCommand)?;
     match subcommand.as_str() {
-        "list" => Ok(Command::ListContainers),
+        "list" => parse_list_containers(source),
         "delete" => Ok(Command::DeleteContainer(
             source
                 .next()
                 .ok_or(ArgumentParsingError::MissingContainerName)?,
         )),
+        "kill" => parse_kill_container(source),
+        "pause" => Ok(Command::PauseContainer(
+            source
+                .next()
+                .ok_or(ArgumentParsingError::MissingContainerName)?,
+        )),
+        "unpause" => Ok(Command::UnpauseContainer(
+            source
+                .next()
+                .ok_or(ArgumentParsingError::MissingContainerName)?,
+        )),
+        "stats" => parse_stats(source),
+        "stop" => parse_stop_container(source),
+        "update" => parse_update_container(source),
         c => Err(ArgumentParsingError::InvalidImageSubCommand(c.to_owned())),
     }
 }
 
+fn parse_filter(filter: &str) -> Result<Filter, ArgumentParsingError> {
+    Filter::from_str(filter).map_err(|_| ArgumentParsingError::InvalidFilter(filter.to_owned()))
+}
+
+fn parse_format(format: &str) -> Result<ListFormat, ArgumentParsingError> {
+    ListFormat::from_str(format).map_err(|_| ArgumentParsingError::InvalidFormat(format.to_owned()))
+}
+
+fn parse_list_containers<I: Iterator<Item = String>>(
+    mut source: I,
+) -> Result<Command, ArgumentParsingError> {
+    let mut options = ListOptions::default();
+    while let Some(s) = source.next() {
+        match s.as_str() {
+            "-a" | "--all" => options.all = true,
+            "--filter" => {
+                let filter = source.next().ok_or(ArgumentParsingError::MissingFilter)?;
+                options.filters.push(parse_filter(&filter)?);
+            }
+            s if s.starts_with(FILTER_OPTION) => {
+                options
+                    .filters
+                    .push(parse_filter(&s.replace(FILTER_OPTION, ""))?);
+            }
+            "--format" => {
+                let format = source.next().ok_or(ArgumentParsingError::MissingFormat)?;
+                options.format = parse_format(&format)?;
+            }
+            s if s.starts_with(FORMAT_OPTION) => {
+                options.format = parse_format(&s.replace(FORMAT_OPTION, ""))?;
+            }
+            s => Err(ArgumentParsingError::InvalidArgument(s.to_owned()))?,
+        }
+    }
+    Ok(Command::ListContainers(options))
+}
+
+// Like 9, KILL or SIGKILL.
+fn parse_signal(signal: &str) -> Result<Signal, ArgumentParsingError> {
+    let invalid = || ArgumentParsingError::InvalidSignal(signal.to_owned());
+    if let Ok(number) = i32::from_str(signal) {
+        return Signal::from_c_int(number).map_err(|_| invalid());
+    }
+    let name = signal.to_uppercase();
+    let name = if name.starts_with("SIG") {
+        name
+    } else {
+        format!("SIG{}", name)
+    };
+    Signal::from_str(&name).map_err(|_| invalid())
+}
+
+fn parse_kill_container<I: Iterator<Item = String>>(
+    mut source: I,
+) -> Result<Command, ArgumentParsingError> {
+    let mut signal = DEFAULT_KILL_SIGNAL;
+    let mut all = false;
+    let mut container = None;
+    while let Some(s) = source.next() {
+        match s.as_str() {
+            "-s" | "--signal" => {
+                signal = parse_signal(&source.next().ok_or(ArgumentParsingError::MissingSignal)?)?;
+            }
+            s if s.starts_with(SIGNAL_OPTION) => {
+                signal = parse_signal(&s[SIGNAL_OPTION.len()..])?;
+            }
+            "-a" | "--all" => all = true,
+            s if s.starts_with('-') => Err(ArgumentParsingError::InvalidArgument(s.to_owned()))?,
+            _ if container.is_none() => container = Some(s),
+            s => Err(ArgumentParsingError::InvalidArgument(s.to_owned()))?,
+        }
+    }
+    Ok(Command::KillContainer {
+        container: container.ok_or(ArgumentParsingError::MissingContainerName)?,
+        signal,
+        all,
+    })
+}
+
+fn parse_stats<I: Iterator<Item = String>>(mut source: I) -> Result<Command, ArgumentParsingError> {
+    let mut options = StatsOptions::default();
+    let mut containers = vec![];
+    while let Some(s) = source.next() {
+        match s.as_str() {
+            "--no-stream" => options.no_stream = true,
+            "--format" => {
+                let format = source.next().ok_or(ArgumentParsingError::MissingFormat)?;
+                options.format = parse_format(&format)?;
+            }
+            s if s.starts_with(FORMAT_OPTION) => {
+                options.format = parse_format(&s.replace(FORMAT_OPTION, ""))?;
+            }
+            s if s.starts_with('-') => Err(ArgumentParsingError::InvalidArgument(s.to_owned()))?,
+            _ => containers.push(s),
+        }
+    }
+    Ok(Command::Stats {
+        containers,
+        options,
+    })
+}
+
+// Takes the same resource options as ruthless run.
+fn parse_update_container<I: Iterator<Item = String>>(
+    source: I,
+) -> Result<Command, ArgumentParsingError> {
+    let mut resource_options = vec![];
+    let mut resources = vec![];
+    let mut container = None;
+    for argument in source {
+        match argument.as_str() {
+            s if s.starts_with("--") => {
+                parse_cgroup_option(s, &mut resource_options)?;
+                resources.push(argument.clone());
+            }
+            _ if container.is_none() => container = Some(argument),
+            s => Err(ArgumentParsingError::InvalidArgument(s.to_owned()))?,
+        }
+    }
+    if resources.is_empty() {
+        Err(ArgumentParsingError::MissingResourceOption)?
+    }
+    Ok(Command::UpdateContainer {
+        container: container.ok_or(ArgumentParsingError::MissingContainerName)?,
+        resource_options,
+        resources,
+    })
+}
+
+fn parse_time(time: &str) -> Result<Duration, ArgumentParsingError> {
+    u64::from_str(time)
+        .map(Duration::from_secs)
+        .map_err(|_| ArgumentParsingError::CantParseNumber(time.to_owned()))
+}
+
+fn parse_stop_container<I: Iterator<Item = String>>(
+    mut source: I,
+) -> Result<Command, ArgumentParsingError> {
+    let mut signal = DEFAULT_STOP_SIGNAL;
+    let mut time = DEFAULT_STOP_TIMEOUT;
+    let mut container = None;
+    while let Some(s) = source.next() {
+        match s.as_str() {
+            "-s" | "--signal" => {
+                signal = parse_signal(&source.next().ok_or(ArgumentParsingError::MissingSignal)?)?;
+            }
+            s if s.starts_with(SIGNAL_OPTION) => {
+                signal = parse_signal(&s[SIGNAL_OPTION.len()..])?;
+            }
+            "-t" | "--time" => {
+                time = parse_time(&source.next().ok_or(ArgumentParsingError::MissingTime)?)?;
+            }
+            s if s.starts_with(TIME_OPTION) => time = parse_time(&s[TIME_OPTION.len()..])?,
+            s if s.starts_with('-') => Err(ArgumentParsingError::InvalidArgument(s.to_owned()))?,
+            _ if container.is_none() => container = Some(s),
+            s => Err(ArgumentParsingError::InvalidArgument(s.to_owned()))?,
+        }
+    }
+    Ok(Command::StopContainer {
+        container: container.ok_or(ArgumentParsingError::MissingContainerName)?,
+        signal,
+        time,
+    })
+}
+
 fn parse_image_subcommand<I: Iterator<Item = String>>(
     mut source: I,
 ) -> Result<Command, ArgumentParsingError> {
@@ -215,18 +550,237 @@ fn parse_cgroup_option(
     Ok(())
 }
 
+fn parse_network_mode(mode: &str) -> Result<NetworkMode, ArgumentParsingError> {
+    NetworkMode::from_str(mode).map_err(ArgumentParsingError::InvalidNetwork)
+}
+
+fn parse_port_mapping(mapping: &str) -> Result<PortMapping, ArgumentParsingError> {
+    PortMapping::from_str(mapping).map_err(ArgumentParsingError::InvalidNetwork)
+}
+
+fn parse_capability(capability: &str) -> Result<Capability, ArgumentParsingError> {
+    Capability::from_str(capability)
+        .map_err(|_| ArgumentParsingError::InvalidCapability(capability.to_owned()))
+}
+
+fn parse_security_option(option: &str) -> Result<SeccompOption, ArgumentParsingError> {
+    if !option.starts_with(SECCOMP_SECURITY_OPT) {
+        Err(ArgumentParsingError::InvalidSecurityOption(
+            option.to_owned(),
+        ))?
+    }
+    SeccompOption::from_str(&option.replacen(SECCOMP_SECURITY_OPT, "", 1))
+        .map_err(|_| ArgumentParsingError::InvalidSecurityOption(option.to_owned()))
+}
+
+fn parse_ulimit(ulimit: &str) -> Result<Ulimit, ArgumentParsingError> {
+    Ulimit::from_str(ulimit)
+        .map_err(|e| ArgumentParsingError::InvalidUlimit(ulimit.to_owned(), e.to_string()))
+}
+
+fn parse_env_file(path: &str) -> Result<Vec<String>, ArgumentParsingError> {
+    let content = read_to_string(path).map_err(|e| {
+        ArgumentParsingError::InvalidEnvironmentFile(path.to_owned(), e.to_string())
+    })?;
+    Ok(content
+        .lines()
+        .map(|l| l.trim_start())
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(|l| l.to_owned())
+        .collect())
+}
+
+fn parse_volume(volume: &str) -> Result<Volume, ArgumentParsingError> {
+    Volume::from_str(volume)
+        .map_err(|e| ArgumentParsingError::InvalidVolume(volume.to_owned(), e.to_string()))
+}
+
+fn parse_mount(mount: &str) -> Result<Volume, ArgumentParsingError> {
+    Volume::from_mount_option(mount)
+        .map_err(|e| ArgumentParsingError::InvalidVolume(mount.to_owned(), e.to_string()))
+}
+
+fn parse_tmpfs(tmpfs: &str) -> Result<Tmpfs, ArgumentParsingError> {
+    Tmpfs::from_str(tmpfs)
+        .map_err(|e| ArgumentParsingError::InvalidTmpfs(tmpfs.to_owned(), e.to_string()))
+}
+
+fn parse_size(size: &str) -> Result<u64, ArgumentParsingError> {
+    let invalid = || ArgumentParsingError::InvalidSize(size.to_owned());
+    let lowercase = size.to_lowercase();
+    let number = lowercase.trim_end_matches('b');
+    let (number, multiplier) = match number.chars().last() {
+        Some('k') => (&number[..number.len() - 1], 1 << 10),
+        Some('m') => (&number[..number.len() - 1], 1 << 20),
+        Some('g') => (&number[..number.len() - 1], 1 << 30),
+        _ => (number, 1),
+    };
+    let size = u64::from_str(number).map_err(|_| invalid())?;
+    match size.checked_mul(multiplier) {
+        Some(0) | None => Err(invalid()),
+        Some(size) => Ok(size),
+    }
+}
+
+fn parse_log_driver(driver: &str) -> Result<LogDriver, ArgumentParsingError> {
+    LogDriver::from_str(driver)
+        .map_err(|_| ArgumentParsingError::InvalidLogDriver(driver.to_owned()))
+}
+
+fn parse_log_option(option: &str, log: &mut LogConfig) -> Result<(), ArgumentParsingError> {
+    if let Some(size) = option.strip_prefix(MAX_SIZE_LOG_OPT) {
+        log.max_size = Some(parse_size(size)?);
+    } else if let Some(max_file) = option.strip_prefix(MAX_FILE_LOG_OPT) {
+        log.max_file = match usize::from_str(max_file) {
+            Ok(0) | Err(_) => Err(ArgumentParsingError::InvalidLogOption(option.to_owned()))?,
+            Ok(max_file) => Some(max_file),
+        };
+    } else {
+        Err(ArgumentParsingError::InvalidLogOption(option.to_owned()))?
+    }
+    Ok(())
+}
+
+fn parse_workdir(workdir: String) -> Result<String, ArgumentParsingError> {
+    if workdir.starts_with('/') {
+        Ok(workdir)
+    } else {
+        Err(ArgumentParsingError::InvalidWorkdir(workdir))
+    }
+}
+
 fn parse_run_subcommand<I: Iterator<Item = String>>(
-    mut source: I,
+    source: I,
 ) -> Result<Command, ArgumentParsingError> {
+    let arguments: Vec<String> = source.collect();
+    let mut source = arguments.iter().cloned();
+    let mut added_capabilities = Vec::new();
     let mut command = Vec::new();
-    let mut detach = false;
+    let mut dropped_capabilities = Vec::new();
+    let mut entrypoint = None;
     let mut image = None;
+    let mut jail_options = JailOptions::default();
     let mut name = None;
+    let mut privileged = false;
     let mut resource_options = Vec::new();
+    let mut seccomp = None;
     while let Some(s) = source.next() {
         match (s.as_str(), &image) {
             ("-d", _) | ("--detach", _) if command.is_empty() => {
-                detach = true;
+                jail_options.detach = true;
+            }
+            (s, _) if command.is_empty() && s.starts_with(ENTRYPOINT_OPTION) => {
+                entrypoint = Some(s.replace(ENTRYPOINT_OPTION, ""));
+            }
+            ("-e", _) | ("--env", _) if command.is_empty() => {
+                let variable = source
+                    .next()
+                    .ok_or(ArgumentParsingError::MissingEnvironmentVariable)?;
+                jail_options.env.push(variable);
+            }
+            (s, _) if command.is_empty() && s.starts_with(ENV_OPTION) => {
+                jail_options.env.push(s.replace(ENV_OPTION, ""));
+            }
+            ("--env-file", _) if command.is_empty() => {
+                let path = source
+                    .next()
+                    .ok_or(ArgumentParsingError::MissingEnvironmentFile)?;
+                jail_options.env.extend(parse_env_file(&path)?);
+            }
+            (s, _) if command.is_empty() && s.starts_with(ENV_FILE_OPTION) => {
+                jail_options
+                    .env
+                    .extend(parse_env_file(&s.replace(ENV_FILE_OPTION, ""))?);
+            }
+            ("-w", _) | ("--workdir", _) if command.is_empty() => {
+                let workdir = source.next().ok_or(ArgumentParsingError::MissingWorkdir)?;
+                jail_options.workdir = Some(parse_workdir(workdir)?);
+            }
+            (s, _) if command.is_empty() && s.starts_with(WORKDIR_OPTION) => {
+                jail_options.workdir = Some(parse_workdir(s.replace(WORKDIR_OPTION, ""))?);
+            }
+            ("-v", _) | ("--volume", _) if command.is_empty() => {
+                let volume = source.next().ok_or(ArgumentParsingError::MissingVolume)?;
+                jail_options.volumes.push(parse_volume(&volume)?);
+            }
+            (s, _) if command.is_empty() && s.starts_with(VOLUME_OPTION) => {
+                jail_options
+                    .volumes
+                    .push(parse_volume(&s.replace(VOLUME_OPTION, ""))?);
+            }
+            ("--mount", _) if command.is_empty() => {
+                let mount = source.next().ok_or(ArgumentParsingError::MissingVolume)?;
+                jail_options.volumes.push(parse_mount(&mount)?);
+            }
+            (s, _) if command.is_empty() && s.starts_with(MOUNT_OPTION) => {
+                jail_options
+                    .volumes
+                    .push(parse_mount(&s.replace(MOUNT_OPTION, ""))?);
+            }
+            ("--tmpfs", _) if command.is_empty() => {
+                let tmpfs = source.next().ok_or(ArgumentParsingError::MissingTmpfs)?;
+                jail_options.tmpfs.push(parse_tmpfs(&tmpfs)?);
+            }
+            (s, _) if command.is_empty() && s.starts_with(TMPFS_OPTION) => {
+                jail_options
+                    .tmpfs
+                    .push(parse_tmpfs(&s.replace(TMPFS_OPTION, ""))?);
+            }
+            ("--shm-size", _) if command.is_empty() => {
+                let size = source.next().ok_or(ArgumentParsingError::MissingShmSize)?;
+                jail_options.shm_size = Some(parse_size(&size)?);
+            }
+            (s, _) if command.is_empty() && s.starts_with(SHM_SIZE_OPTION) => {
+                jail_options.shm_size = Some(parse_size(&s.replace(SHM_SIZE_OPTION, ""))?);
+            }
+            ("--log-driver", _) if command.is_empty() => {
+                let driver = source
+                    .next()
+                    .ok_or(ArgumentParsingError::MissingLogDriver)?;
+                jail_options.log.driver = parse_log_driver(&driver)?;
+            }
+            (s, _) if command.is_empty() && s.starts_with(LOG_DRIVER_OPTION) => {
+                jail_options.log.driver = parse_log_driver(&s.replace(LOG_DRIVER_OPTION, ""))?;
+            }
+            ("--log-opt", _) if command.is_empty() => {
+                let option = source
+                    .next()
+                    .ok_or(ArgumentParsingError::MissingLogOption)?;
+                parse_log_option(&option, &mut jail_options.log)?;
+            }
+            (s, _) if command.is_empty() && s.starts_with(LOG_OPT_OPTION) => {
+                parse_log_option(&s.replace(LOG_OPT_OPTION, ""), &mut jail_options.log)?;
+            }
+            ("-u", _) | ("--user", _) if command.is_empty() => {
+                jail_options.user = Some(source.next().ok_or(ArgumentParsingError::MissingUser)?);
+            }
+            (s, _) if command.is_empty() && s.starts_with(USER_OPTION) => {
+                jail_options.user = Some(s.replace(USER_OPTION, ""));
+            }
+            ("--privileged", _) if command.is_empty() => {
+                privileged = true;
+            }
+            ("-i", _) | ("--interactive", _) if command.is_empty() => {
+                jail_options.interactive = true;
+            }
+            ("-t", _) | ("--tty", _) if command.is_empty() => {
+                jail_options.tty = true;
+            }
+            ("-it", _) | ("-ti", _) if command.is_empty() => {
+                jail_options.interactive = true;
+                jail_options.tty = true;
+            }
+            ("--init", _) if command.is_empty() => {
+                jail_options.init = true;
+            }
+            ("--read-only", _) if command.is_empty() => {
+                jail_options.read_only = true;
+            }
+            (s, _) if command.is_empty() && s.starts_with(CAP_ADD_OPTION) => {
+                added_capabilities.push(parse_capability(&s.replace(CAP_ADD_OPTION, ""))?);
+            }
+            (s, _) if command.is_empty() && s.starts_with(CAP_DROP_OPTION) => {
+                dropped_capabilities.push(parse_capability(&s.replace(CAP_DROP_OPTION, ""))?);
             }
             ("-n", _) if command.is_empty() => {
                 name = Some(
@@ -238,6 +792,44 @@ fn parse_run_subcommand<I: Iterator<Item = String>>(
             (s, _) if command.is_empty() && s.starts_with("--name=") => {
                 name = Some(s.replace("--name=", "").to_owned());
             }
+            ("--security-opt", _) if command.is_empty() => {
+                let option = source
+                    .next()
+                    .ok_or(ArgumentParsingError::MissingSecurityOption)?;
+                seccomp = Some(parse_security_option(&option)?);
+            }
+            (s, _) if command.is_empty() && s.starts_with(SECURITY_OPT_OPTION) => {
+                seccomp = Some(parse_security_option(&s.replace(SECURITY_OPT_OPTION, ""))?);
+            }
+            ("--ulimit", _) if command.is_empty() => {
+                let ulimit = source.next().ok_or(ArgumentParsingError::MissingUlimit)?;
+                jail_options.ulimits.push(parse_ulimit(&ulimit)?);
+            }
+            (s, _) if command.is_empty() && s.starts_with(ULIMIT_OPTION) => {
+                jail_options
+                    .ulimits
+                    .push(parse_ulimit(&s.replace(ULIMIT_OPTION, ""))?);
+            }
+            ("-p", _) | ("--publish", _) if command.is_empty() => {
+                let mapping = source
+                    .next()
+                    .ok_or(ArgumentParsingError::MissingPortMapping)?;
+                jail_options.publish.push(parse_port_mapping(&mapping)?);
+            }
+            (s, _) if command.is_empty() && s.starts_with(PUBLISH_OPTION) => {
+                jail_options
+                    .publish
+                    .push(parse_port_mapping(&s.replace(PUBLISH_OPTION, ""))?);
+            }
+            ("--network", _) if command.is_empty() => {
+                let mode = source
+                    .next()
+                    .ok_or(ArgumentParsingError::MissingNetworkMode)?;
+                jail_options.network = parse_network_mode(&mode)?;
+            }
+            (s, _) if command.is_empty() && s.starts_with(NETWORK_OPTION) => {
+                jail_options.network = parse_network_mode(&s.replace(NETWORK_OPTION, ""))?;
+            }
             (s, _) if command.is_empty() && s.starts_with("--") => {
                 parse_cgroup_option(s, &mut resource_options)?;
             }
@@ -247,12 +839,83 @@ fn parse_run_subcommand<I: Iterator<Item = String>>(
             }
         }
     }
+    if !jail_options.publish.is_empty() && !jail_options.network.isolated() {
+        Err(ArgumentParsingError::PublishWithHostNetwork)?
+    }
+    if (jail_options.log.max_size.is_some() || jail_options.log.max_file.is_some())
+        && jail_options.log.driver != LogDriver::JsonFile
+    {
+        Err(ArgumentParsingError::LogOptionWithoutJsonFile)?
+    }
+    jail_options.seccomp = seccomp.unwrap_or(if privileged {
+        SeccompOption::Unconfined
+    } else {
+        SeccompOption::Default
+    });
+    jail_options.privileged = privileged;
+    jail_options.capabilities = if privileged {
+        Capabilities::all()
+    } else {
+        Capabilities::new(&added_capabilities, &dropped_capabilities)
+    };
+    let image = image.ok_or(ArgumentParsingError::MissingImage)?;
+    // Everything before the image.
+    let options = arguments[..arguments.len() - command.len() - 1].to_vec();
     Ok(Command::Run {
         command,
-        detach,
+        entrypoint,
+        image,
+        jail_options: Box::new(jail_options),
         name,
+        options,
         resource_options,
-        image: image.ok_or(ArgumentParsingError::MissingImage)?,
+    })
+}
+
+fn parse_attach<I: Iterator<Item = String>>(
+    mut source: I,
+) -> Result<Command, ArgumentParsingError> {
+    let mut detach_keys = DEFAULT_DETACH_KEYS.to_owned();
+    let container = loop {
+        match source.next() {
+            Some(ref s) if s.starts_with(DETACH_KEYS_OPTION) => {
+                detach_keys = s[DETACH_KEYS_OPTION.len()..].to_owned();
+            }
+            Some(s) => break s,
+            None => Err(ArgumentParsingError::MissingContainerName)?,
+        }
+    };
+    Ok(Command::Attach {
+        container,
+        detach_keys: parse_detach_keys(&detach_keys)
+            .ok_or(ArgumentParsingError::InvalidDetachKeys(detach_keys))?,
+    })
+}
+
+fn parse_exec<I: Iterator<Item = String>>(mut source: I) -> Result<Command, ArgumentParsingError> {
+    let mut interactive = false;
+    let mut tty = false;
+    let container = loop {
+        match source.next() {
+            Some(ref s) if s == "-i" || s == "--interactive" => interactive = true,
+            Some(ref s) if s == "-t" || s == "--tty" => tty = true,
+            Some(ref s) if s == "-it" || s == "-ti" => {
+                interactive = true;
+                tty = true;
+            }
+            Some(s) => break s,
+            None => Err(ArgumentParsingError::MissingContainerName)?,
+        }
+    };
+    let command: Vec<String> = source.collect();
+    if command.is_empty() {
+        Err(ArgumentParsingError::MissingExecCommand)?
+    }
+    Ok(Command::Exec {
+        container,
+        command,
+        interactive,
+        tty,
     })
 }
 
@@ -263,17 +926,56 @@ fn parse_help<I: Iterator<Item = String>>(source: I) -> Result<Command, Argument
     } else {
         let command = next_arguments.join(" ");
         Some(match command.as_str() {
-            "run" | "image list" | "image delete" => command,
+            "run" | "attach" | "exec" | "logs" | "container list" | "container delete"
+            | "container kill" | "container pause" | "container unpause" | "container stats"
+            | "container stop" | "container update" | "image list" | "image delete" => command,
             _ => Err(ArgumentParsingError::UnexpectedCommand(command))?,
         })
     }))
 }
 
+// Like 10 or all.
+fn parse_tail(tail: &str) -> Result<Option<usize>, ArgumentParsingError> {
+    match tail {
+        "all" => Ok(None),
+        tail => usize::from_str(tail)
+            .map(Some)
+            .map_err(|_| ArgumentParsingError::CantParseNumber(tail.to_owned())),
+    }
+}
+
+fn parse_since_option(since: &str) -> Result<DateTime<Utc>, ArgumentParsingError> {
+    parse_since(since).ok_or_else(|| ArgumentParsingError::InvalidSince(since.to_owned()))
+}
+
 fn parse_logs<I: Iterator<Item = String>>(mut source: I) -> Result<Command, ArgumentParsingError> {
-    let container = source
-        .next()
-        .ok_or(ArgumentParsingError::MissingContainerName)?;
-    Ok(Command::Logs(container))
+    let mut options = LogsOptions::default();
+    let mut container = None;
+    while let Some(s) = source.next() {
+        match s.as_str() {
+            "-f" | "--follow" => options.follow = true,
+            "-t" | "--timestamps" => options.timestamps = true,
+            "-n" | "--tail" => {
+                options.tail =
+                    parse_tail(&source.next().ok_or(ArgumentParsingError::MissingTail)?)?;
+            }
+            s if s.starts_with(TAIL_OPTION) => options.tail = parse_tail(&s[TAIL_OPTION.len()..])?,
+            "--since" => {
+                let since = source.next().ok_or(ArgumentParsingError::MissingTime)?;
+                options.since = Some(parse_since_option(&since)?);
+            }
+            s if s.starts_with(SINCE_OPTION) => {
+                options.since = Some(parse_since_option(&s[SINCE_OPTION.len()..])?);
+            }
+            s if s.starts_with('-') => Err(ArgumentParsingError::InvalidArgument(s.to_owned()))?,
+            _ if container.is_none() => container = Some(s),
+            s => Err(ArgumentParsingError::InvalidArgument(s.to_owned()))?,
+        }
+    }
+    Ok(Command::Logs {
+        container: container.ok_or(ArgumentParsingError::MissingContainerName)?,
+        options,
+    })
 }
 
 impl TryFrom<Vec<String>> for Command {
@@ -285,6 +987,7 @@ impl TryFrom<Vec<String>> for Command {
             .next()
             .ok_or(ArgumentParsingError::NotEnoughArguments)?;
         match leading.as_str() {
+            "attach" => parse_attach(source),
             "container" => parse_container_subcommand(source),
             "export" => {
                 let image = source.next().ok_or(ArgumentParsingError::MissingImage)?;
@@ -293,6 +996,7 @@ impl TryFrom<Vec<String>> for Command {
                     .ok_or(ArgumentParsingError::MissingTarballLocation)?;
                 Ok(Command::Export(image, tarball))
             }
+            "exec" => parse_exec(source),
             "help" => parse_help(source),
             "image" => parse_image_subcommand(source),
             "import" => {
@@ -307,3 +1011,63 @@ impl TryFrom<Vec<String>> for Command {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("512").unwrap(), 512);
+        assert_eq!(parse_size("512b").unwrap(), 512);
+        assert_eq!(parse_size("64k").unwrap(), 64 << 10);
+        assert_eq!(parse_size("64m").unwrap(), 64 << 20);
+        assert_eq!(parse_size("64MB").unwrap(), 64 << 20);
+        assert_eq!(parse_size("2g").unwrap(), 2 << 30);
+    }
+
+    #[test]
+    fn rejects_invalid_sizes() {
+        for size in [
+            "",
+            "0",
+            "0m",
+            "m",
+            "-1",
+            "1.5g",
+            "64t",
+            "18446744073709551615k",
+        ]
+        .iter()
+        {
+            assert!(
+                matches!(parse_size(size), Err(ArgumentParsingError::InvalidSize(_))),
+                "{}",
+                size
+            );
+        }
+    }
+
+    #[test]
+    fn parses_signals() {
+        for signal in ["9", "KILL", "kill", "SIGKILL", "sigkill"].iter() {
+            assert_eq!(parse_signal(signal).unwrap(), Signal::SIGKILL, "{}", signal);
+        }
+        assert_eq!(parse_signal("15").unwrap(), Signal::SIGTERM);
+        assert_eq!(parse_signal("HUP").unwrap(), Signal::SIGHUP);
+    }
+
+    #[test]
+    fn rejects_invalid_signals() {
+        for signal in ["", "0", "-9", "65", "SIG", "SIGFOO", "SIGSIGKILL", "9s"].iter() {
+            assert!(
+                matches!(
+                    parse_signal(signal),
+                    Err(ArgumentParsingError::InvalidSignal(_))
+                ),
+                "{}",
+                signal
+            );
+        }
+    }
+}
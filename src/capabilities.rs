@@ -0,0 +1,249 @@
+use failure::Error;
+use nix::libc::{
+    c_int, c_ulong, prctl, syscall, SYS_capset, PR_CAPBSET_DROP, PR_CAP_AMBIENT,
+    PR_CAP_AMBIENT_CLEAR_ALL, PR_CAP_AMBIENT_RAISE,
+};
+use serde::{Deserialize, Serialize};
+use std::fs::read_to_string;
+use std::io;
+use std::str::FromStr;
+
+const CAP_LAST_CAP_FILE: &str = "/proc/sys/kernel/cap_last_cap";
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+const CAPABILITY_PREFIX: &str = "CAP_";
+const ALL_CAPABILITIES: &str = "ALL";
+const CAPABILITIES: &[&str] = &[
+    "CHOWN",
+    "DAC_OVERRIDE",
+    "DAC_READ_SEARCH",
+    "FOWNER",
+    "FSETID",
+    "KILL",
+    "SETGID",
+    "SETUID",
+    "SETPCAP",
+    "LINUX_IMMUTABLE",
+    "NET_BIND_SERVICE",
+    "NET_BROADCAST",
+    "NET_ADMIN",
+    "NET_RAW",
+    "IPC_LOCK",
+    "IPC_OWNER",
+    "SYS_MODULE",
+    "SYS_RAWIO",
+    "SYS_CHROOT",
+    "SYS_PTRACE",
+    "SYS_PACCT",
+    "SYS_ADMIN",
+    "SYS_BOOT",
+    "SYS_NICE",
+    "SYS_RESOURCE",
+    "SYS_TIME",
+    "SYS_TTY_CONFIG",
+    "MKNOD",
+    "LEASE",
+    "AUDIT_WRITE",
+    "AUDIT_CONTROL",
+    "SETFCAP",
+    "MAC_OVERRIDE",
+    "MAC_ADMIN",
+    "SYSLOG",
+    "WAKE_ALARM",
+    "BLOCK_SUSPEND",
+    "AUDIT_READ",
+    "PERFMON",
+    "BPF",
+    "CHECKPOINT_RESTORE",
+];
+const DEFAULT_CAPABILITIES: &[&str] = &[
+    "CHOWN",
+    "DAC_OVERRIDE",
+    "FSETID",
+    "FOWNER",
+    "MKNOD",
+    "NET_RAW",
+    "SETGID",
+    "SETUID",
+    "SETFCAP",
+    "SETPCAP",
+    "NET_BIND_SERVICE",
+    "SYS_CHROOT",
+    "KILL",
+    "AUDIT_WRITE",
+];
+
+#[derive(Debug, Fail)]
+pub(crate) enum CapabilityError {
+    #[fail(display = "Unknown capability {}", 0)]
+    UnknownCapability(String),
+}
+
+#[repr(C)]
+struct CapUserHeader {
+    version: u32,
+    pid: c_int,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct CapUserData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Capability {
+    All,
+    Single(usize),
+}
+
+impl FromStr for Capability {
+    type Err = CapabilityError;
+
+    fn from_str(s: &str) -> Result<Capability, Self::Err> {
+        let name = s.to_uppercase();
+        if name == ALL_CAPABILITIES {
+            return Ok(Capability::All);
+        }
+        let name = name.trim_start_matches(CAPABILITY_PREFIX);
+        CAPABILITIES
+            .iter()
+            .position(|c| *c == name)
+            .map(Capability::Single)
+            .ok_or_else(|| CapabilityError::UnknownCapability(s.to_owned()))
+    }
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize)]
+pub(crate) struct Capabilities {
+    set: u64,
+}
+
+impl Default for Capabilities {
+    fn default() -> Capabilities {
+        let set = DEFAULT_CAPABILITIES
+            .iter()
+            .filter_map(|name| CAPABILITIES.iter().position(|c| c == name))
+            .fold(0, |set, capability| set | 1 << capability);
+        Capabilities { set }
+    }
+}
+
+impl Capabilities {
+    pub(crate) fn new(added: &[Capability], dropped: &[Capability]) -> Capabilities {
+        let mut capabilities = Capabilities::default();
+        if added.contains(&Capability::All) {
+            added.iter().for_each(|c| capabilities.add(*c));
+            dropped.iter().for_each(|c| capabilities.remove(*c));
+        } else {
+            dropped.iter().for_each(|c| capabilities.remove(*c));
+            added.iter().for_each(|c| capabilities.add(*c));
+        }
+        capabilities
+    }
+
+    pub(crate) fn contains(&self, name: &str) -> bool {
+        match Capability::from_str(name) {
+            Ok(Capability::All) => self.set == !0,
+            Ok(Capability::Single(c)) => self.set & (1 << c) != 0,
+            Err(_) => false,
+        }
+    }
+
+    pub(crate) fn all() -> Capabilities {
+        Capabilities { set: !0 }
+    }
+
+    fn add(&mut self, capability: Capability) {
+        match capability {
+            Capability::All => self.set = !0,
+            Capability::Single(c) => self.set |= 1 << c,
+        }
+    }
+
+    fn remove(&mut self, capability: Capability) {
+        match capability {
+            Capability::All => self.set = 0,
+            Capability::Single(c) => self.set &= !(1 << c),
+        }
+    }
+
+    pub(crate) fn for_kernel(self) -> Result<KernelCapabilities, Error> {
+        let last_capability = usize::from_str(read_to_string(CAP_LAST_CAP_FILE)?.trim())?;
+        Ok(KernelCapabilities {
+            last_capability,
+            set: self.set & ((1 << (last_capability + 1)) - 1),
+        })
+    }
+}
+
+#[derive(Clone, Copy)]
+pub(crate) struct KernelCapabilities {
+    last_capability: usize,
+    set: u64,
+}
+
+#[inline]
+fn check_result(result: i64) -> io::Result<()> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+impl KernelCapabilities {
+    // Needs CAP_SETPCAP, so it runs before switching to the user of the container. Like apply, it
+    // runs between fork and exec and must not allocate.
+    pub(crate) fn drop_bounding(self) -> io::Result<()> {
+        for capability in 0..=self.last_capability {
+            if self.set & (1 << capability) == 0 {
+                check_result(unsafe {
+                    i64::from(prctl(PR_CAPBSET_DROP, capability as c_ulong, 0, 0, 0))
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    // Runs after switching to the user of the container, which keeps the permitted set but loses
+    // the effective one.
+    pub(crate) fn apply(self) -> io::Result<()> {
+        let header = CapUserHeader {
+            version: LINUX_CAPABILITY_VERSION_3,
+            pid: 0,
+        };
+        let mut data = [CapUserData::default(); 2];
+        for (i, d) in data.iter_mut().enumerate() {
+            let word = (self.set >> (32 * i)) as u32;
+            d.effective = word;
+            d.permitted = word;
+            d.inheritable = word;
+        }
+        check_result(unsafe { syscall(SYS_capset, &header, data.as_ptr()) })?;
+        check_result(unsafe {
+            i64::from(prctl(
+                PR_CAP_AMBIENT,
+                PR_CAP_AMBIENT_CLEAR_ALL as c_ulong,
+                0,
+                0,
+                0,
+            ))
+        })?;
+        for capability in 0..=self.last_capability {
+            if self.set & (1 << capability) != 0 {
+                check_result(unsafe {
+                    i64::from(prctl(
+                        PR_CAP_AMBIENT,
+                        PR_CAP_AMBIENT_RAISE as c_ulong,
+                        capability as c_ulong,
+                        0,
+                        0,
+                    ))
+                })?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,20 +1,37 @@
 use crate::mount::MOUNTS_FILE;
 use failure::Error;
 use nix::errno::Errno;
-use nix::sys::signal::{kill, SIGTERM};
-use nix::unistd::{getuid, Pid};
+use nix::fcntl::{open, OFlag};
+use nix::libc::{unlinkat, AT_REMOVEDIR};
+use nix::sys::signal::{kill, Signal, SIGKILL};
+use nix::sys::stat::Mode;
+use nix::unistd::{close, getpid, getuid, write as write_fd, Pid};
 use nix::Error as SyscallError;
+use std::ffi::CString;
 use std::fs::{create_dir, read_dir, read_to_string, remove_dir, write, DirEntry};
+use std::io;
+use std::os::unix::io::RawFd;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
 
 #[derive(Debug, Fail)]
 enum CgroupError {
     #[fail(display = "cgroup2 not mounted")]
     CgroupNotMounted,
+    #[fail(display = "The cgroup freezer isn't supported by this kernel")]
+    FreezerNotSupported,
 }
 
 const CGROUP_PROCS: &str = "cgroup.procs";
+const CGROUP_EVENTS: &str = "cgroup.events";
+const CGROUP_KILL: &str = "cgroup.kill";
+const CGROUP_FREEZE: &str = "cgroup.freeze";
+const POPULATED_EVENT: &str = "populated";
+const FROZEN_EVENT: &str = "frozen";
+const EVENTS_POLL_INTERVAL: Duration = Duration::from_millis(100);
+const CURRENT_PROCESS: &[u8] = b"0";
 const CGROUP_FS: &str = "cgroup2";
 
 fn find_cgroups_path() -> Result<Option<String>, Error> {
@@ -42,6 +59,10 @@ fn get_ruthless_cgroup_path() -> Result<PathBuf, Error> {
 
 pub(crate) fn get_active_cgroups() -> Result<Vec<String>, Error> {
     let containers_location = get_ruthless_cgroup_path()?;
+    // Created along with the first container.
+    if !containers_location.exists() {
+        return Ok(vec![]);
+    }
     let cgroup_content: Vec<DirEntry> =
         read_dir(containers_location)?.collect::<Result<Vec<DirEntry>, _>>()?;
     let mut result = Vec::new();
@@ -60,23 +81,106 @@ pub(crate) fn get_active_cgroups() -> Result<Vec<String>, Error> {
     Ok(result)
 }
 
-pub(crate) fn terminate_cgroup_processes(container_name: &str) -> Result<(), Error> {
+fn get_processes_cgroup_path(container_name: &str) -> Result<PathBuf, Error> {
     let containers_location = get_ruthless_cgroup_path()?;
-    let container_location = containers_location.join(format!(
+    Ok(containers_location.join(format!(
         "{}-core/{}-processes",
         container_name, container_name
-    ));
-    let pids: Vec<i32> = read_to_string(container_location.join("cgroup.procs"))?
-        .trim()
+    )))
+}
+
+pub(crate) fn get_container_processes(container_name: &str) -> Result<Vec<Pid>, Error> {
+    let container_location = get_processes_cgroup_path(container_name)?;
+    Ok(read_to_string(container_location.join(CGROUP_PROCS))?
         .split('\n')
-        .map(|p| i32::from_str(p))
-        .collect::<Result<Vec<i32>, _>>()?;
-    for p in pids {
-        match kill(Pid::from_raw(p), SIGTERM) {
-            Ok(()) => {}
-            Err(SyscallError::Sys(Errno::ESRCH)) => {}
+        .filter(|p| !p.is_empty())
+        .map(|p| i32::from_str(p).map(Pid::from_raw))
+        .collect::<Result<Vec<Pid>, _>>()?)
+}
+
+// The cgroup of a container, with the one of its processes and the ones of `ruthless exec` below.
+pub(crate) fn get_container_cgroup_path(container_name: &str) -> Result<PathBuf, Error> {
+    Ok(get_ruthless_cgroup_path()?.join(format!("{}-core", container_name)))
+}
+
+fn read_event(cgroup: &Path, event: &str) -> Result<bool, Error> {
+    Ok(read_to_string(cgroup.join(CGROUP_EVENTS))?
+        .lines()
+        .any(|l| l == format!("{} 1", event)))
+}
+
+// Sends [signal] to every process of the container, including the ones started by `ruthless exec`.
+pub(crate) fn signal_cgroup_processes(container_name: &str, signal: Signal) -> Result<(), Error> {
+    for entry in read_dir(get_container_cgroup_path(container_name)?)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        for pid in read_to_string(entry.path().join(CGROUP_PROCS))?.lines() {
+            match kill(Pid::from_raw(i32::from_str(pid)?), signal) {
+                Ok(()) | Err(SyscallError::Sys(Errno::ESRCH)) => {}
+                Err(e) => Err(e)?,
+            }
+        }
+    }
+    Ok(())
+}
+
+// Polls cgroup.events until no process is left in the container or [timeout] passes. Returns
+// whether the container is empty.
+pub(crate) fn wait_until_empty(
+    container_name: &str,
+    timeout: Option<Duration>,
+) -> Result<bool, Error> {
+    let cgroup = get_container_cgroup_path(container_name)?;
+    let start = Instant::now();
+    loop {
+        let populated = match read_event(&cgroup, POPULATED_EVENT) {
+            Ok(populated) => populated,
+            // The monitor removes the cgroup once the container finished.
+            Err(_) if !cgroup.exists() => false,
             Err(e) => Err(e)?,
+        };
+        if !populated {
+            return Ok(true);
         }
+        if timeout.is_some_and(|t| start.elapsed() >= t) {
+            return Ok(false);
+        }
+        sleep(EVENTS_POLL_INTERVAL);
+    }
+}
+
+// Kills every process of the container and waits for them. Kernels without cgroup.kill get the
+// container frozen first, so that no process can fork while the rest are being killed.
+pub(crate) fn kill_cgroup_processes(container_name: &str) -> Result<(), Error> {
+    let cgroup = get_container_cgroup_path(container_name)?;
+    if cgroup.join(CGROUP_KILL).exists() {
+        write(cgroup.join(CGROUP_KILL), "1")?;
+        wait_until_empty(container_name, None)?;
+        return Ok(());
+    }
+    while !wait_until_empty(container_name, Some(EVENTS_POLL_INTERVAL))? {
+        write(cgroup.join(CGROUP_FREEZE), "1")?;
+        while !read_event(&cgroup, FROZEN_EVENT)? {
+            sleep(EVENTS_POLL_INTERVAL);
+        }
+        signal_cgroup_processes(container_name, SIGKILL)?;
+        // Anything that survived keeps running instead of staying frozen.
+        write(cgroup.join(CGROUP_FREEZE), "0")?;
+    }
+    Ok(())
+}
+
+// Freezes or thaws the processes of the container, waiting until cgroup.events reports it.
+pub(crate) fn freeze_container(container_name: &str, frozen: bool) -> Result<(), Error> {
+    let cgroup = get_processes_cgroup_path(container_name)?;
+    if !cgroup.join(CGROUP_FREEZE).exists() {
+        Err(CgroupError::FreezerNotSupported)?
+    }
+    write(cgroup.join(CGROUP_FREEZE), if frozen { "1" } else { "0" })?;
+    while read_event(&cgroup, FROZEN_EVENT)? != frozen {
+        sleep(EVENTS_POLL_INTERVAL);
     }
     Ok(())
 }
@@ -114,65 +218,22 @@ impl CgroupFactory {
 
     pub(crate) fn build(&self) -> Result<Cgroup, Error> {
         let cgroup = Cgroup::new(self.name.as_str())?;
-        for o in self.options.iter() {
-            match o {
-                CgroupOptions::CpuMax(max, period) => {
-                    cgroup.set_cpu_max(max.as_str(), *period as isize)?;
-                }
-                CgroupOptions::CpuWeight(weight) => {
-                    cgroup.set_cpu_weight(weight)?;
-                }
-                CgroupOptions::CpuWeightNice(weight) => {
-                    cgroup.set_cpu_weight_nice(weight)?;
-                }
-                CgroupOptions::CpusetCpus(cpus) => {
-                    cgroup.set_cpuset_cpus(cpus.as_str())?;
-                }
-                CgroupOptions::CpusetCpusPartition(cpus) => {
-                    cgroup.set_cpuset_cpus_partition(cpus.as_str())?;
-                }
-                CgroupOptions::CpusetMems(mems) => {
-                    cgroup.set_cpuset_mems(mems.as_str())?;
-                }
-                CgroupOptions::IoMax(max) => {
-                    cgroup.set_io_max(max.as_str())?;
-                }
-                CgroupOptions::IoWeight(range, weight) => {
-                    cgroup.set_io_weight(range.as_str(), *weight as isize)?;
-                }
-                CgroupOptions::MemoryHigh(high) => {
-                    cgroup.set_memory_high(high.as_str())?;
-                }
-                CgroupOptions::MemoryLow(low) => {
-                    cgroup.set_memory_low(low)?;
-                }
-                CgroupOptions::MemoryMax(max) => {
-                    cgroup.set_memory_max(max.as_str())?;
-                }
-                CgroupOptions::MemoryMin(min) => {
-                    cgroup.set_memory_min(min)?;
-                }
-                CgroupOptions::MemoryOomGroup(group) => {
-                    cgroup.set_memory_oom_group(group)?;
-                }
-                CgroupOptions::MemorySwapMax(max) => {
-                    cgroup.set_memory_swap_max(max.as_str())?;
-                }
-                CgroupOptions::PidsMax(max) => {
-                    cgroup.set_pids_max(max)?;
-                }
-                CgroupOptions::RdmaMax(max) => {
-                    cgroup.set_rdma_max(max.as_str())?;
-                }
-            }
-        }
+        cgroup.apply(&self.options)?;
         Ok(cgroup)
     }
 }
 
+// Changes the limits of a running container.
+pub(crate) fn update_cgroup(container_name: &str, options: &[CgroupOptions]) -> Result<(), Error> {
+    Cgroup::open(container_name)?.apply(options)
+}
+
+// [owned] is false for the cgroup of a container that was started by another ruthless, which
+// outlives this one.
 pub(crate) struct Cgroup {
     parent: PathBuf,
     path: PathBuf,
+    owned: bool,
 }
 
 macro_rules! cgroup_controller_interface_string_number {
@@ -204,7 +265,75 @@ impl Cgroup {
         create_dir(&parent)?;
         create_dir(&path)?;
 
-        Ok(Cgroup { parent, path })
+        Ok(Cgroup {
+            parent,
+            path,
+            owned: true,
+        })
+    }
+
+    fn open(name: &str) -> Result<Cgroup, Error> {
+        Ok(Cgroup {
+            parent: get_container_cgroup_path(name)?,
+            path: get_processes_cgroup_path(name)?,
+            owned: false,
+        })
+    }
+
+    fn apply(&self, options: &[CgroupOptions]) -> Result<(), Error> {
+        for o in options.iter() {
+            match o {
+                CgroupOptions::CpuMax(max, period) => {
+                    self.set_cpu_max(max.as_str(), *period as isize)?;
+                }
+                CgroupOptions::CpuWeight(weight) => {
+                    self.set_cpu_weight(weight)?;
+                }
+                CgroupOptions::CpuWeightNice(weight) => {
+                    self.set_cpu_weight_nice(weight)?;
+                }
+                CgroupOptions::CpusetCpus(cpus) => {
+                    self.set_cpuset_cpus(cpus.as_str())?;
+                }
+                CgroupOptions::CpusetCpusPartition(cpus) => {
+                    self.set_cpuset_cpus_partition(cpus.as_str())?;
+                }
+                CgroupOptions::CpusetMems(mems) => {
+                    self.set_cpuset_mems(mems.as_str())?;
+                }
+                CgroupOptions::IoMax(max) => {
+                    self.set_io_max(max.as_str())?;
+                }
+                CgroupOptions::IoWeight(range, weight) => {
+                    self.set_io_weight(range.as_str(), *weight as isize)?;
+                }
+                CgroupOptions::MemoryHigh(high) => {
+                    self.set_memory_high(high.as_str())?;
+                }
+                CgroupOptions::MemoryLow(low) => {
+                    self.set_memory_low(low)?;
+                }
+                CgroupOptions::MemoryMax(max) => {
+                    self.set_memory_max(max.as_str())?;
+                }
+                CgroupOptions::MemoryMin(min) => {
+                    self.set_memory_min(min)?;
+                }
+                CgroupOptions::MemoryOomGroup(group) => {
+                    self.set_memory_oom_group(group)?;
+                }
+                CgroupOptions::MemorySwapMax(max) => {
+                    self.set_memory_swap_max(max.as_str())?;
+                }
+                CgroupOptions::PidsMax(max) => {
+                    self.set_pids_max(max)?;
+                }
+                CgroupOptions::RdmaMax(max) => {
+                    self.set_rdma_max(max.as_str())?;
+                }
+            }
+        }
+        Ok(())
     }
 
     cgroup_controller_interface!(self, usize, "cpu.weight", set_cpu_weight);
@@ -237,7 +366,74 @@ impl Cgroup {
 
 impl Drop for Cgroup {
     fn drop(&mut self) {
+        if !self.owned {
+            return;
+        }
         remove_dir(self.path.clone()).unwrap();
+        // Leftovers of the processes started with `ruthless exec`, which have just died with the
+        // pid namespace of the container.
+        if let Ok(entries) = read_dir(&self.parent) {
+            entries
+                .filter_map(|e| e.ok())
+                .filter(|e| e.file_type().map(|t| t.is_dir()).unwrap_or(false))
+                .for_each(|e| remove_dir(e.path()).unwrap_or(()));
+        }
         remove_dir(self.parent.clone()).unwrap();
     }
 }
+
+// A leaf cgroup next to the one of the container processes for the commands started by `ruthless
+// exec`. It keeps descriptors into the cgroup tree, as the tree isn't reachable once the mount
+// namespace of the container is joined.
+pub(crate) struct ExecCgroup {
+    parent: RawFd,
+    name: CString,
+    procs: RawFd,
+}
+
+impl ExecCgroup {
+    pub(crate) fn new(container_name: &str) -> Result<ExecCgroup, Error> {
+        let parent_path = get_ruthless_cgroup_path()?.join(format!("{}-core", container_name));
+        let name = format!("{}-exec-{}", container_name, getpid());
+        let path = parent_path.join(&name);
+        create_dir(&path)?;
+        let parent = open(
+            &parent_path,
+            OFlag::O_RDONLY | OFlag::O_DIRECTORY | OFlag::O_CLOEXEC,
+            Mode::empty(),
+        )?;
+        let procs = open(
+            &path.join(CGROUP_PROCS),
+            OFlag::O_WRONLY | OFlag::O_CLOEXEC,
+            Mode::empty(),
+        )?;
+        Ok(ExecCgroup {
+            parent,
+            name: CString::new(name)?,
+            procs,
+        })
+    }
+
+    pub(crate) fn procs(&self) -> RawFd {
+        self.procs
+    }
+}
+
+// Moves the calling process into the cgroup of [procs]. It runs between fork and exec, so it
+// must not allocate.
+pub(crate) fn join_cgroup(procs: RawFd) -> io::Result<()> {
+    write_fd(procs, CURRENT_PROCESS).map_err(|_| io::Error::last_os_error())?;
+    Ok(())
+}
+
+impl Drop for ExecCgroup {
+    fn drop(&mut self) {
+        close(self.procs).unwrap();
+        // Processes left behind by the command keep the cgroup busy until the container stops.
+        match Errno::result(unsafe { unlinkat(self.parent, self.name.as_ptr(), AT_REMOVEDIR) }) {
+            Ok(_) | Err(SyscallError::Sys(Errno::EBUSY)) => {}
+            Err(e) => panic!("{}", e),
+        }
+        close(self.parent).unwrap();
+    }
+}
@@ -0,0 +1,208 @@
+use crate::cgroup::{get_container_processes, join_cgroup, ExecCgroup};
+use crate::idmap::IdMap;
+use crate::jail::{exit_code, CONTAINER_PATH, DEFAULT_WORKDIR};
+use crate::state::ContainerState;
+use crate::tty::Pty;
+use crate::user::User;
+use failure::Error;
+use nix::fcntl::{open, OFlag};
+use nix::sched::{setns, CloneFlags};
+use nix::sys::stat::Mode;
+use nix::unistd::{close, Pid};
+use std::fs::{read, read_link, read_to_string};
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+const PATH_ENV_VARIABLE: &str = "PATH";
+const NSPID_FIELD: &str = "NSpid:";
+// The user namespace goes first to get the capabilities needed to join the rest, and the mount
+// namespace last as it hides the /proc of the host.
+const NAMESPACES: &[(&str, CloneFlags)] = &[
+    ("user", CloneFlags::CLONE_NEWUSER),
+    ("ipc", CloneFlags::CLONE_NEWIPC),
+    ("uts", CloneFlags::CLONE_NEWUTS),
+    ("net", CloneFlags::CLONE_NEWNET),
+    ("pid", CloneFlags::CLONE_NEWPID),
+    ("mnt", CloneFlags::CLONE_NEWNS),
+];
+
+#[derive(Debug, Fail)]
+pub(crate) enum ExecError {
+    #[fail(display = "Container {} isn't running", 0)]
+    ContainerNotRunning(String),
+}
+
+// The init of the container is the process of its cgroup that is PID 1 in its innermost pid
+// namespace.
+fn find_init(processes: &[Pid]) -> Option<Pid> {
+    processes.iter().cloned().find(|pid| {
+        read_to_string(format!("/proc/{}/status", pid))
+            .ok()
+            .and_then(|status| {
+                status
+                    .lines()
+                    .find(|l| l.starts_with(NSPID_FIELD))
+                    .and_then(|l| l.split_whitespace().last().map(|p| p == "1"))
+            })
+            .unwrap_or(false)
+    })
+}
+
+fn parent_pid(pid: Pid) -> Option<Pid> {
+    let stat = read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The command name can contain spaces and parentheses, so fields are counted after it.
+    let fields = stat[stat.rfind(')')? + 1..]
+        .split_whitespace()
+        .collect::<Vec<&str>>();
+    i32::from_str(fields.get(1)?).ok().map(Pid::from_raw)
+}
+
+// The command the container was started with is a child of its init.
+fn find_command(init: Pid, processes: &[Pid]) -> Option<Pid> {
+    processes
+        .iter()
+        .cloned()
+        .find(|pid| parent_pid(*pid) == Some(init))
+}
+
+// The process of [container] that signals are meant for. The init of the container only gets the
+// signals it handles, so they go to the command instead, unless it's the one running as init.
+pub(crate) fn find_main_process(container: &str) -> Result<Pid, Error> {
+    let processes = get_container_processes(container)
+        .map_err(|_| ExecError::ContainerNotRunning(container.to_owned()))?;
+    let init = find_init(&processes)
+        .ok_or_else(|| ExecError::ContainerNotRunning(container.to_owned()))?;
+    Ok(find_command(init, &processes).unwrap_or(init))
+}
+
+fn container_environment(init: Pid, processes: &[Pid]) -> Vec<(String, String)> {
+    find_command(init, processes)
+        .and_then(|pid| read(format!("/proc/{}/environ", pid)).ok())
+        .map(|environ| {
+            String::from_utf8_lossy(&environ)
+                .split('\0')
+                .filter_map(|variable| {
+                    let i = variable.find('=')?;
+                    Some((variable[..i].to_owned(), variable[i + 1..].to_owned()))
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// Namespaces that are shared with the host, like the network one in host mode, are skipped as
+// joining them again isn't allowed from inside the user namespace of the container.
+fn join_namespaces(pid: Pid) -> Result<(), Error> {
+    let mut namespaces = vec![];
+    for (namespace, flag) in NAMESPACES.iter() {
+        let path = format!("/proc/{}/ns/{}", pid, namespace);
+        if read_link(&path)? == read_link(format!("/proc/self/ns/{}", namespace))? {
+            continue;
+        }
+        namespaces.push((
+            open(
+                path.as_str(),
+                OFlag::O_RDONLY | OFlag::O_CLOEXEC,
+                Mode::empty(),
+            )?,
+            *flag,
+        ));
+    }
+    for (fd, flag) in namespaces {
+        let result = setns(fd, flag);
+        close(fd)?;
+        result?;
+    }
+    Ok(())
+}
+
+pub(crate) fn exec(
+    container: &str,
+    command: &[String],
+    interactive: bool,
+    tty: bool,
+) -> Result<isize, Error> {
+    let processes = get_container_processes(container)
+        .map_err(|_| ExecError::ContainerNotRunning(container.to_owned()))?;
+    let init = find_init(&processes)
+        .ok_or_else(|| ExecError::ContainerNotRunning(container.to_owned()))?;
+    let environment = container_environment(init, &processes);
+    // Commands get the same confinement as the payload. Everything that reads files of the host
+    // happens before joining the mount namespace of the container.
+    let confinement = ContainerState::load(container)?
+        .map(|s| s.confinement)
+        .unwrap_or_default();
+    let seccomp_filter = confinement.seccomp.compile(&confinement.capabilities)?;
+    let capabilities = confinement.capabilities.for_kernel()?;
+    for ulimit in confinement.ulimits.iter() {
+        ulimit.check()?;
+    }
+    let id_map = IdMap::new();
+    let cgroup = ExecCgroup::new(container)?;
+    let pty = if tty { Some(Pty::open()?) } else { None };
+    join_namespaces(init)?;
+    let user_spec = confinement.user.as_ref().filter(|u| !u.is_empty());
+    let mut user = User::resolve(user_spec.map_or("", |u| u.as_str()))?;
+    user.restrict_to(id_map);
+    let workdir = confinement
+        .workdir
+        .as_ref()
+        .filter(|w| !w.is_empty())
+        .map_or(DEFAULT_WORKDIR, |w| w.as_str());
+    let mut process = Command::new(&command[0]);
+    process
+        .args(command[1..].iter())
+        .env_clear()
+        .env(PATH_ENV_VARIABLE, CONTAINER_PATH)
+        .envs(environment)
+        .current_dir(workdir);
+    let procs = cgroup.procs();
+    unsafe {
+        process.pre_exec(move || join_cgroup(procs));
+    }
+    match pty {
+        Some(pty) => {
+            process
+                .stdin(pty.slave_stdio()?)
+                .stdout(pty.slave_stdio()?)
+                .stderr(pty.slave_stdio()?);
+            unsafe {
+                process.pre_exec(Pty::prepare_payload);
+            }
+        }
+        None if !interactive => {
+            process.stdin(Stdio::null());
+        }
+        None => {}
+    }
+    for ulimit in confinement.ulimits {
+        unsafe {
+            process.pre_exec(move || ulimit.apply());
+        }
+    }
+    unsafe {
+        process.pre_exec(move || capabilities.drop_bounding());
+    }
+    if user_spec.is_some() {
+        unsafe {
+            process.pre_exec(move || user.switch());
+        }
+    }
+    unsafe {
+        process.pre_exec(move || capabilities.apply());
+    }
+    if let Some(filter) = seccomp_filter {
+        unsafe {
+            process.pre_exec(move || filter.install());
+        }
+    }
+    let mut child = process.spawn()?;
+    drop(process);
+    if let Some(pty) = pty {
+        pty.close_slave()?;
+        pty.relay(interactive)?;
+        pty.close_master()?;
+    }
+    Ok(exit_code(child.wait()?))
+}
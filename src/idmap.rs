@@ -0,0 +1,138 @@
+use failure::Error;
+use nix::unistd::{getgid, getuid, Gid, Pid, Uid};
+use std::fs::{read_to_string, write};
+use std::process::Command;
+use std::str::FromStr;
+
+const PASSWD_FILE: &str = "/etc/passwd";
+const SUBUID_FILE: &str = "/etc/subuid";
+const SUBGID_FILE: &str = "/etc/subgid";
+const NEWUIDMAP_COMMAND: &str = "newuidmap";
+const NEWGIDMAP_COMMAND: &str = "newgidmap";
+const SETGROUPS_DENY: &str = "deny";
+// The container root plus 65535 subordinate ids.
+const SUBORDINATE_IDS: u32 = 65535;
+
+#[derive(Debug, Fail)]
+pub(crate) enum IdMapError {
+    #[fail(display = "{} failed to write the id map of process {}", 0, 1)]
+    HelperFailed(String, Pid),
+}
+
+#[derive(Clone, Copy)]
+struct Range {
+    start: u32,
+    count: u32,
+}
+
+fn user_name(uid: Uid) -> Option<String> {
+    read_to_string(PASSWD_FILE)
+        .ok()?
+        .lines()
+        .map(|l| l.split(':').collect::<Vec<&str>>())
+        .find(|fields| fields.len() > 2 && fields[2] == uid.to_string())
+        .map(|fields| fields[0].to_owned())
+}
+
+fn subordinate_range(path: &str, id: u32, name: &Option<String>) -> Option<Range> {
+    read_to_string(path)
+        .ok()?
+        .lines()
+        .filter_map(|l| match l.split(':').collect::<Vec<&str>>().as_slice() {
+            [owner, start, count] => Some((
+                owner.to_string(),
+                u32::from_str(start).ok()?,
+                u32::from_str(count).ok()?,
+            )),
+            _ => None,
+        })
+        .find(|(owner, _, count)| {
+            *count > 0 && (*owner == id.to_string() || Some(owner) == name.as_ref())
+        })
+        .map(|(_, start, count)| Range {
+            start,
+            count: count.min(SUBORDINATE_IDS),
+        })
+}
+
+fn run_helper(helper: &str, pid: Pid, id: u32, range: Range) -> Result<(), Error> {
+    let status = Command::new(helper)
+        .arg(pid.to_string())
+        .args(["0", &id.to_string(), "1"])
+        .args(["1", &range.start.to_string(), &range.count.to_string()])
+        .status()?;
+    if !status.success() {
+        Err(IdMapError::HelperFailed(helper.to_owned(), pid))?
+    }
+    Ok(())
+}
+
+// Maps root in the container to the user running ruthless and, when /etc/subuid and /etc/subgid
+// assign it subordinate ranges, the rest of the ids to them through the setuid helpers of
+// shadow-utils. Without ranges only root is mapped.
+#[derive(Clone, Copy)]
+pub(crate) struct IdMap {
+    uid: Uid,
+    gid: Gid,
+    subordinate_ranges: Option<(Range, Range)>,
+}
+
+impl IdMap {
+    pub(crate) fn new() -> IdMap {
+        let uid = getuid();
+        let gid = getgid();
+        let name = user_name(uid);
+        let subordinate_ranges = match (
+            subordinate_range(SUBUID_FILE, uid.as_raw(), &name),
+            subordinate_range(SUBGID_FILE, uid.as_raw(), &name),
+        ) {
+            (Some(uids), Some(gids)) => Some((uids, gids)),
+            _ => None,
+        };
+        IdMap {
+            uid,
+            gid,
+            subordinate_ranges,
+        }
+    }
+
+    // Supplementary groups can only be changed in the container when the gid map was written by
+    // newgidmap, as an unprivileged process has to deny setgroups to write its own map.
+    pub(crate) fn allows_setgroups(&self) -> bool {
+        self.subordinate_ranges.is_some()
+    }
+
+    // Whether [gid] of the container has a gid on the host.
+    pub(crate) fn maps_gid(&self, gid: u32) -> bool {
+        match self.subordinate_ranges {
+            Some((_, gids)) => gid <= gids.count,
+            None => gid == 0,
+        }
+    }
+
+    pub(crate) fn write(&self, pid: Pid) -> Result<(), Error> {
+        match self.subordinate_ranges {
+            Some((uids, gids)) => {
+                run_helper(NEWUIDMAP_COMMAND, pid, self.uid.as_raw(), uids)?;
+                run_helper(NEWGIDMAP_COMMAND, pid, self.gid.as_raw(), gids)?;
+            }
+            None => {
+                eprintln!(
+                    "Warning: no subordinate ids configured for uid {} in {} and {}, only root \
+                     will be mapped in the container",
+                    self.uid, SUBUID_FILE, SUBGID_FILE
+                );
+                write(
+                    format!("/proc/{}/uid_map", pid),
+                    format!("0 {} 1\n", self.uid),
+                )?;
+                write(format!("/proc/{}/setgroups", pid), SETGROUPS_DENY)?;
+                write(
+                    format!("/proc/{}/gid_map", pid),
+                    format!("0 {} 1\n", self.gid),
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,4 +1,3 @@
-use crate::jaillogs::LOGS_PATH;
 use crate::mount::MOUNTS_FILE;
 use dirs::home_dir;
 use failure::Error;
@@ -7,7 +6,9 @@ use nix::errno::Errno;
 use nix::fcntl::OFlag;
 use nix::sys::stat::Mode;
 use nix::Error as SyscallError;
-use std::fs::{metadata, read, read_dir, remove_dir_all, remove_file, File};
+use serde::{Deserialize, Serialize};
+use serde_json::{from_str, to_string};
+use std::fs::{metadata, read, read_dir, read_to_string, remove_dir_all, remove_file, write, File};
 use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
 use tar::{Archive, Entry};
@@ -24,6 +25,7 @@ const BTRFS_VOL_NAME_MAX: usize = 255;
 const BTRFS_UUID_SIZE: usize = 16;
 const BTRFS_INO_LOOKUP_PATH_MAX: usize = 4080;
 const LIB_LOCATION: &str = ".local/lib/ruthless/images";
+const IMAGE_CONFIG_EXTENSION: &str = "json";
 
 #[repr(C)]
 #[derive(Clone, Copy)]
@@ -176,6 +178,36 @@ pub(crate) enum ImageError {
     OsStringConversionError(PathBuf),
 }
 
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub(crate) struct ImageConfig {
+    #[serde(rename = "Cmd", default)]
+    pub(crate) cmd: Option<Vec<String>>,
+    #[serde(rename = "Entrypoint", default)]
+    pub(crate) entrypoint: Option<Vec<String>>,
+    #[serde(rename = "Env", default)]
+    pub(crate) env: Option<Vec<String>>,
+    #[serde(rename = "User", default)]
+    pub(crate) user: Option<String>,
+    #[serde(rename = "WorkingDir", default)]
+    pub(crate) working_dir: Option<String>,
+}
+
+impl ImageConfig {
+    pub(crate) fn command(&self, entrypoint: &Option<String>, command: &[String]) -> Vec<String> {
+        let mut result = match entrypoint {
+            Some(e) if e.is_empty() => Vec::new(),
+            Some(e) => vec![e.to_owned()],
+            None => self.entrypoint.clone().unwrap_or_default(),
+        };
+        if !command.is_empty() {
+            result.extend(command.iter().cloned());
+        } else if entrypoint.is_none() {
+            result.extend(self.cmd.clone().unwrap_or_default());
+        }
+        result
+    }
+}
+
 fn get_image_repository_path() -> Result<PathBuf, Error> {
     let home_path = home_dir().ok_or(ImageError::NoHomeDirectory)?;
     let lib_path = home_path.join(LIB_LOCATION);
@@ -293,10 +325,6 @@ impl ImageRepository {
         Ok(ImageRepository { path })
     }
 
-    pub(crate) fn get_logs_path(&self, container: &str) -> PathBuf {
-        self.path.join(container).join(LOGS_PATH[1..].to_owned())
-    }
-
     pub(crate) fn get_image_location_for_process(
         &self,
         image: &str,
@@ -343,9 +371,32 @@ impl ImageRepository {
         let repository = Dir::open(&self.path, OFlag::O_DIRECTORY, Mode::S_IRWXU)?;
         let args = BtrfsVolArgs::new(-1i64, name);
         unsafe { btrfs_ioc_snap_delete(repository.as_raw_fd() as i32, &args) }?;
+        let config_path = self.get_image_config_path(name);
+        if config_path.is_file() {
+            remove_file(config_path)?;
+        }
         Ok(())
     }
 
+    pub(crate) fn get_image_config(&self, name: &str) -> Result<ImageConfig, Error> {
+        let config_path = self.get_image_config_path(name);
+        if name.contains('/') || !config_path.is_file() {
+            Ok(ImageConfig::default())
+        } else {
+            Ok(from_str(read_to_string(config_path)?.as_str())?)
+        }
+    }
+
+    pub(crate) fn save_image_config(&self, name: &str, config: &ImageConfig) -> Result<(), Error> {
+        write(self.get_image_config_path(name), to_string(config)?)?;
+        Ok(())
+    }
+
+    fn get_image_config_path(&self, name: &str) -> PathBuf {
+        self.path
+            .join(format!("{}.{}", name, IMAGE_CONFIG_EXTENSION))
+    }
+
     pub(crate) fn create_image_from_path(&self, name: &str, path: &PathBuf) -> Result<(), Error> {
         let subvolume_path = self.create_image_subvolume(name)?;
         let mut layer_content = Archive::new(File::open(path)?);
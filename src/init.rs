@@ -0,0 +1,89 @@
+use crate::jail::SIGNAL_EXIT_CODE_BASE;
+use failure::Error;
+use nix::errno::Errno;
+use nix::sys::signal::{kill, SigSet, Signal};
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::{getpgrp, getpid, setpgid, Pid};
+use nix::Error as SyscallError;
+use std::io;
+
+// Signals that are either uncatchable or raised synchronously by the faults of the init itself.
+const UNFORWARDED_SIGNALS: &[Signal] = &[
+    Signal::SIGKILL,
+    Signal::SIGSTOP,
+    Signal::SIGABRT,
+    Signal::SIGBUS,
+    Signal::SIGFPE,
+    Signal::SIGILL,
+    Signal::SIGSEGV,
+    Signal::SIGSYS,
+    Signal::SIGTRAP,
+    Signal::SIGTTIN,
+    Signal::SIGTTOU,
+    Signal::SIGTSTP,
+];
+
+// A minimal PID 1 for the container. The jail child stays around instead of only waiting for the
+// payload: it forwards the signals it gets to the process group of the payload, reaps the orphans
+// that are reparented to it and finishes with the exit status of the payload.
+#[derive(Clone, Copy)]
+pub(crate) struct Init {
+    signals: SigSet,
+}
+
+impl Init {
+    // Signals are blocked before spawning the payload so none is lost before the init starts
+    // waiting for them.
+    pub(crate) fn new() -> Result<Init, Error> {
+        let mut signals = SigSet::empty();
+        Signal::iterator()
+            .filter(|s| !UNFORWARDED_SIGNALS.contains(s))
+            .for_each(|s| signals.add(s));
+        signals.thread_block()?;
+        Ok(Init { signals })
+    }
+
+    // Runs between fork and exec of the payload. The signal mask is already reset by std. With a
+    // tty the payload already leads its own session, and so its own process group.
+    pub(crate) fn prepare_payload(self) -> io::Result<()> {
+        if getpgrp() == getpid() {
+            return Ok(());
+        }
+        setpgid(Pid::from_raw(0), Pid::from_raw(0)).map_err(|_| io::Error::last_os_error())
+    }
+
+    pub(crate) fn supervise(self, payload: Pid) -> Result<isize, Error> {
+        loop {
+            match self.signals.wait()? {
+                Signal::SIGCHLD => {
+                    if let Some(exit_code) = reap(payload)? {
+                        return Ok(exit_code);
+                    }
+                }
+                signal => match kill(Pid::from_raw(-payload.as_raw()), signal) {
+                    Ok(_) | Err(SyscallError::Sys(Errno::ESRCH)) => {}
+                    Err(e) => Err(e)?,
+                },
+            }
+        }
+    }
+}
+
+fn reap(payload: Pid) -> Result<Option<isize>, Error> {
+    let mut exit_code = None;
+    loop {
+        match waitpid(None, Some(WaitPidFlag::WNOHANG)) {
+            Ok(WaitStatus::Exited(pid, code)) if pid == payload => {
+                exit_code = Some(code as isize);
+            }
+            Ok(WaitStatus::Signaled(pid, signal, _)) if pid == payload => {
+                exit_code = Some(SIGNAL_EXIT_CODE_BASE + signal as isize);
+            }
+            Ok(WaitStatus::StillAlive) | Err(SyscallError::Sys(Errno::ECHILD)) => {
+                return Ok(exit_code)
+            }
+            Ok(_) => {}
+            Err(e) => Err(e)?,
+        }
+    }
+}
@@ -1,89 +1,359 @@
+use crate::capabilities::Capabilities;
 use crate::cgroup::CgroupFactory;
-use crate::jaillogs::JailLogs;
-use crate::mount::Mount;
+use crate::idmap::IdMap;
+use crate::images::ImageConfig;
+use crate::init::Init;
+use crate::jaillogs::{monitor_log_path, LogConfig};
+use crate::monitor::{Monitor, Pipes};
+use crate::mount::{
+    make_mounts_private, make_root_read_only, mask_paths, mount_devices, mount_sysfs, switch_root,
+    Mount, Tmpfs, Volume, DEFAULT_SHM_SIZE,
+};
+use crate::network::{bring_up_loopback, NetworkMode, PortForwarder, PortMapping};
+use crate::seccomp::{SeccompFilter, SeccompOption};
+use crate::state::{update_own_state, ContainerState};
+use crate::tty::Pty;
+use crate::ulimit::Ulimit;
+use crate::user::User;
 use failure::Error;
+use nix::fcntl::{open, OFlag};
+use nix::libc::{STDERR_FILENO, STDIN_FILENO, STDOUT_FILENO};
 use nix::sched::{clone, CloneFlags};
 use nix::sys::signal::SIGCHLD;
-use nix::sys::wait::waitpid;
-use nix::unistd::{chroot, getpid, getuid, setuid, Pid, Uid};
-use std::fs::write;
-use std::process::Command;
+use nix::sys::stat::Mode;
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::{
+    close, dup2, getpid, pipe2, read, setgid, setgroups, setsid, setuid, write as write_fd, Gid,
+    Pid, Uid,
+};
+use serde::{Deserialize, Serialize};
+use std::env::var_os;
+use std::fs::{create_dir_all, read_to_string};
+use std::io::ErrorKind;
+use std::os::unix::io::RawFd;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::process::{Command, ExitStatus, Stdio};
 
 const STACK_SIZE: usize = 65536;
-const PROC_UID_MAP_FILE: &str = "/proc/self/uid_map";
 const PATH_ENV_VARIABLE: &str = "PATH";
-const CONTAINER_PATH: &str = "/bin:/usr/bin:/usr/local/bin:/sbin:/usr/sbin:/usr/local/sbin";
+const HOME_ENV_VARIABLE: &str = "HOME";
+pub(crate) const DEFAULT_WORKDIR: &str = "/";
+pub(crate) const CONTAINER_PATH: &str =
+    "/bin:/usr/bin:/usr/local/bin:/sbin:/usr/sbin:/usr/local/sbin";
 const COMMAND_ERROR: &str = "Command failed to start";
+pub(crate) const SIGNAL_EXIT_CODE_BASE: isize = 128;
+const SETUP_FAILURE_EXIT_CODE: isize = 125;
+const COMMAND_NOT_EXECUTABLE_EXIT_CODE: isize = 126;
+const COMMAND_NOT_FOUND_EXIT_CODE: isize = 127;
 const PROC_RESOURCE: &str = "proc";
 const PROC_TARGET: &str = "/proc";
 const PROC_FS: &str = "proc";
+const NULL_DEVICE: &str = "/dev/null";
 
-fn set_user_map(user_id: Uid) -> Result<(), Error> {
-    let content = format!("0 {} 1\n", user_id);
-    write(PROC_UID_MAP_FILE, content)?;
+#[derive(Debug, Fail)]
+enum JailError {
+    #[fail(display = "The other end of the jail finished before synchronizing")]
+    SynchronizationFailed,
+    #[fail(display = "No command given and the image doesn't define one")]
+    MissingCommand,
+}
+
+fn become_root(id_map: IdMap) -> Result<(), Error> {
+    if id_map.allows_setgroups() {
+        setgroups(&[Gid::from_raw(0)])?;
+    }
+    setgid(Gid::from_raw(0))?;
+    setuid(Uid::from_raw(0))?;
+    Ok(())
+}
+
+fn notify(fd: RawFd) -> Result<(), Error> {
+    write_fd(fd, &[0])?;
+    close(fd)?;
+    Ok(())
+}
+
+fn wait_for(fd: RawFd) -> Result<(), Error> {
+    let mut buffer = [0; 1];
+    let read_bytes = read(fd, &mut buffer)?;
+    close(fd)?;
+    if read_bytes == 0 {
+        Err(JailError::SynchronizationFailed)?
+    }
     Ok(())
 }
 
-fn run(run_args: &[String], redirect_logs: bool) -> Result<isize, Error> {
+// Leaves the standard streams of ruthless, so that it can exit and whatever reads its output see the
+// end of it while the container runs on.
+fn detach_stdio(container: &str) -> Result<(), Error> {
+    let null = open(
+        NULL_DEVICE,
+        OFlag::O_RDONLY | OFlag::O_CLOEXEC,
+        Mode::empty(),
+    )?;
+    let log = open(
+        &monitor_log_path(container)?,
+        OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_TRUNC | OFlag::O_CLOEXEC,
+        Mode::from_bits_truncate(0o600),
+    )?;
+    dup2(null, STDIN_FILENO)?;
+    dup2(log, STDOUT_FILENO)?;
+    dup2(log, STDERR_FILENO)?;
+    close(null)?;
+    close(log)?;
+    Ok(())
+}
+
+fn run(
+    run_args: &[String],
+    options: &JailOptions,
+    id_map: IdMap,
+    seccomp_filter: &Option<SeccompFilter>,
+    pty: Option<Pty>,
+    pipes: Option<Pipes>,
+) -> Result<isize, Error> {
     let _proc_mount = Mount::new(
         PROC_RESOURCE.to_owned(),
         PROC_TARGET.to_owned(),
         PROC_FS.to_owned(),
     )?;
+    // Without a user from the options or the image the payload stays root, with the groups
+    // ruthless became root with.
+    let user_spec = options.user.as_ref().filter(|u| !u.is_empty());
+    let mut user = User::resolve(user_spec.map_or("", |u| u.as_str()))?;
+    user.restrict_to(id_map);
+    let workdir = options
+        .workdir
+        .as_ref()
+        .filter(|w| !w.is_empty())
+        .map_or(DEFAULT_WORKDIR, |w| w.as_str());
+    create_dir_all(workdir)?;
+    let _masked_paths = if options.privileged {
+        vec![]
+    } else {
+        mask_paths()?
+    };
     let mut command = Command::new(run_args[0].clone());
     command
         .args(run_args[1..].iter())
         .env_clear()
         .env(PATH_ENV_VARIABLE, CONTAINER_PATH)
-        .current_dir("/");
-    if redirect_logs {
-        let logs = JailLogs::new()?;
-        command
-            .stdin(logs.stdin()?)
-            .stdout(logs.stdout()?)
-            .stderr(logs.stderr()?);
+        .env(HOME_ENV_VARIABLE, &user.home)
+        .current_dir(workdir);
+    for variable in options.env.iter() {
+        match variable.find('=') {
+            Some(i) => command.env(&variable[..i], &variable[i + 1..]),
+            None => match var_os(variable) {
+                Some(value) => command.env(variable, value),
+                None => command.env_remove(variable),
+            },
+        };
+    }
+    match (pty, pipes) {
+        (Some(pty), _) => {
+            command
+                .stdin(pty.slave_stdio()?)
+                .stdout(pty.slave_stdio()?)
+                .stderr(pty.slave_stdio()?);
+            unsafe {
+                command.pre_exec(Pty::prepare_payload);
+            }
+        }
+        (None, Some(pipes)) => {
+            let (stdin, stdout, stderr) = pipes.payload_stdio()?;
+            command.stdin(stdin).stdout(stdout).stderr(stderr);
+        }
+        (None, None) if !options.interactive => {
+            command.stdin(Stdio::null());
+        }
+        (None, None) => {}
+    }
+    for ulimit in options.ulimits.iter() {
+        ulimit.check()?;
+        let ulimit = ulimit.clone();
+        unsafe {
+            command.pre_exec(move || ulimit.apply());
+        }
+    }
+    let init = if options.init {
+        Some(Init::new()?)
+    } else {
+        None
     };
-    let exit_status = command.spawn().expect(COMMAND_ERROR).wait()?;
-    Ok(exit_status.code().unwrap_or(0) as isize)
+    if let Some(init) = init {
+        unsafe {
+            command.pre_exec(move || init.prepare_payload());
+        }
+    }
+    // The same order as runc: the bounding set is dropped while still root, then the user is
+    // switched and only then the rest of the capability sets are applied.
+    let capabilities = options.capabilities.for_kernel()?;
+    unsafe {
+        command.pre_exec(move || capabilities.drop_bounding());
+    }
+    if user_spec.is_some() {
+        unsafe {
+            command.pre_exec(move || user.switch());
+        }
+    }
+    unsafe {
+        command.pre_exec(move || capabilities.apply());
+    }
+    if let Some(filter) = seccomp_filter.clone() {
+        unsafe {
+            command.pre_exec(move || filter.install());
+        }
+    }
+    if options.read_only {
+        make_root_read_only()?;
+    }
+    match command.spawn() {
+        Ok(mut child) => match init {
+            Some(init) => init.supervise(Pid::from_raw(child.id() as i32)),
+            None => Ok(exit_code(child.wait()?)),
+        },
+        Err(e) => {
+            eprintln!("{}: {}", COMMAND_ERROR, e);
+            Ok(if e.kind() == ErrorKind::NotFound {
+                COMMAND_NOT_FOUND_EXIT_CODE
+            } else {
+                COMMAND_NOT_EXECUTABLE_EXIT_CODE
+            })
+        }
+    }
 }
 
-fn start_parent_process(
-    args: &[String],
-    image: &str,
-    cgroup_factory: &CgroupFactory,
-    user_id: Uid,
-    redirect_logs: bool,
-) -> Result<isize, Error> {
-    let mut stack = [0u8; STACK_SIZE];
-    let cgroup = cgroup_factory.build()?;
-    let pid = clone(
-        Box::new(|| {
-            cgroup.add_pid(getpid().as_raw() as u32).unwrap();
-            set_user_map(user_id).unwrap();
-            setuid(Uid::from_raw(0)).unwrap();
-            chroot(image).unwrap();
-            run(args, redirect_logs).unwrap()
-        }),
-        stack.as_mut(),
-        CloneFlags::CLONE_NEWNS
-            | CloneFlags::CLONE_NEWPID
-            | CloneFlags::CLONE_NEWUTS
-            | CloneFlags::CLONE_NEWUSER,
-        Some(SIGCHLD as i32),
-    )?;
-    waitpid(pid, None)?;
-    Ok(0)
+#[inline]
+pub(crate) fn exit_code(status: ExitStatus) -> isize {
+    match (status.code(), status.signal()) {
+        (Some(code), _) => code as isize,
+        (None, Some(signal)) => SIGNAL_EXIT_CODE_BASE + signal as isize,
+        (None, None) => 0,
+    }
+}
+
+fn wait_exit_code(pid: Pid) -> Result<isize, Error> {
+    Ok(match waitpid(pid, None)? {
+        WaitStatus::Exited(_, code) => code as isize,
+        WaitStatus::Signaled(_, signal, _) => SIGNAL_EXIT_CODE_BASE + signal as isize,
+        _ => 0,
+    })
 }
 
+// Errors can't leave the cloned processes, so they are reported there and turned into an exit
+// code.
+fn report_failure(result: Result<isize, Error>) -> isize {
+    result.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        SETUP_FAILURE_EXIT_CODE
+    })
+}
+
+fn mount_filesystems(root: &str, options: &JailOptions) -> Result<Vec<Mount>, Error> {
+    let mut mounts = mount_devices(root, options.shm_size.unwrap_or(DEFAULT_SHM_SIZE))?;
+    mounts.push(mount_sysfs(root)?);
+    for tmpfs in options.tmpfs.iter() {
+        mounts.push(tmpfs.mount(root)?);
+    }
+    for volume in options.volumes.iter() {
+        mounts.push(volume.mount(root)?);
+    }
+    Ok(mounts)
+}
+
+fn jail_clone_flags(network: NetworkMode) -> CloneFlags {
+    let mut flags = CloneFlags::CLONE_NEWNS
+        | CloneFlags::CLONE_NEWIPC
+        | CloneFlags::CLONE_NEWPID
+        | CloneFlags::CLONE_NEWUTS
+        | CloneFlags::CLONE_NEWUSER;
+    if network.isolated() {
+        flags |= CloneFlags::CLONE_NEWNET;
+    }
+    flags
+}
+
+#[derive(Default)]
+pub(crate) struct JailOptions {
+    pub(crate) capabilities: Capabilities,
+    pub(crate) detach: bool,
+    pub(crate) env: Vec<String>,
+    pub(crate) init: bool,
+    pub(crate) interactive: bool,
+    pub(crate) log: LogConfig,
+    pub(crate) network: NetworkMode,
+    pub(crate) privileged: bool,
+    pub(crate) publish: Vec<PortMapping>,
+    pub(crate) read_only: bool,
+    pub(crate) seccomp: SeccompOption,
+    pub(crate) shm_size: Option<u64>,
+    pub(crate) tmpfs: Vec<Tmpfs>,
+    pub(crate) tty: bool,
+    pub(crate) ulimits: Vec<Ulimit>,
+    pub(crate) user: Option<String>,
+    pub(crate) volumes: Vec<Volume>,
+    pub(crate) workdir: Option<String>,
+}
+
+impl JailOptions {
+    pub(crate) fn apply_image_config(&mut self, config: &ImageConfig) {
+        let mut env = config.env.clone().unwrap_or_default();
+        env.append(&mut self.env);
+        self.env = env;
+        if self.user.is_none() {
+            self.user = config.user.clone();
+        }
+        if self.workdir.is_none() {
+            self.workdir = config.working_dir.clone();
+        }
+    }
+}
+
+// The confinement of the payload that `ruthless exec` applies to its commands too, once the
+// config of the image has been applied.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub(crate) struct Confinement {
+    pub(crate) capabilities: Capabilities,
+    pub(crate) seccomp: SeccompOption,
+    pub(crate) ulimits: Vec<Ulimit>,
+    pub(crate) user: Option<String>,
+    pub(crate) workdir: Option<String>,
+}
+
+impl JailOptions {
+    pub(crate) fn confinement(&self) -> Confinement {
+        Confinement {
+            capabilities: self.capabilities,
+            seccomp: self.seccomp.clone(),
+            ulimits: self.ulimits.clone(),
+            user: self.user.clone(),
+            workdir: self.workdir.clone(),
+        }
+    }
+}
+
+// [state] is only saved once the cgroup of the container claimed its name.
 pub(crate) struct Jail {
-    detach: bool,
-    user_id: Uid,
+    name: String,
+    state: ContainerState,
+    options: JailOptions,
+    id_map: IdMap,
+    pty: Option<Pty>,
+    // Written by the monitor of a detached container once it's serving it.
+    monitor_ready: Option<RawFd>,
 }
 
 impl Jail {
-    pub(crate) fn new(detach: bool) -> Jail {
-        let user_id = getuid();
-        Jail { detach, user_id }
+    pub(crate) fn new(state: ContainerState, options: JailOptions) -> Jail {
+        let id_map = IdMap::new();
+        Jail {
+            name: state.name.clone(),
+            state,
+            options,
+            id_map,
+            pty: None,
+            monitor_ready: None,
+        }
     }
 
     pub(crate) fn run(
@@ -91,12 +361,42 @@ impl Jail {
         args: &[String],
         image: &str,
         cgroup: &CgroupFactory,
-    ) -> Result<(), Error> {
+    ) -> Result<isize, Error> {
+        if args.is_empty() {
+            Err(JailError::MissingCommand)?
+        }
+        if self.options.tty {
+            self.pty = Some(Pty::open()?);
+        }
+        let monitor_ready = if self.options.detach {
+            Some(pipe2(OFlag::O_CLOEXEC)?)
+        } else {
+            None
+        };
+        self.monitor_ready = monitor_ready.map(|(_, write)| write);
         let pid = self.start_process(args, image, cgroup)?;
-        if !self.detach {
-            waitpid(pid, None)?;
+        if let Some((ready_read, ready_write)) = monitor_ready {
+            close(ready_write)?;
+            if let Some(pty) = self.pty {
+                pty.close_master()?;
+                pty.close_slave()?;
+            }
+            // The monitor reports why it couldn't start in its log.
+            if let Err(e) = wait_for(ready_read) {
+                eprint!(
+                    "{}",
+                    read_to_string(monitor_log_path(&self.name)?).unwrap_or_default()
+                );
+                Err(e)?
+            }
+            return Ok(0);
         }
-        Ok(())
+        if let Some(pty) = self.pty {
+            pty.close_slave()?;
+            pty.relay(self.options.interactive)?;
+            pty.close_master()?;
+        }
+        wait_exit_code(pid)
     }
 
     fn start_process(
@@ -106,13 +406,119 @@ impl Jail {
         cgroup: &CgroupFactory,
     ) -> Result<Pid, Error> {
         let mut stack = [0u8; STACK_SIZE];
-        let user_id = self.user_id;
         let pid = clone(
-            Box::new(|| start_parent_process(args, image, cgroup, user_id, self.detach).unwrap()),
+            Box::new(|| {
+                let exit_code = report_failure(self.start_parent_process(args, image, cgroup));
+                report_failure(
+                    update_own_state(&self.name, &self.state.id, |s| s.finish(exit_code))
+                        .map(|_| exit_code),
+                )
+            }),
             stack.as_mut(),
             CloneFlags::empty(),
             Some(SIGCHLD as i32),
         )?;
         Ok(pid)
     }
+
+    fn start_parent_process(
+        &self,
+        args: &[String],
+        image: &str,
+        cgroup_factory: &CgroupFactory,
+    ) -> Result<isize, Error> {
+        let mut stack = [0u8; STACK_SIZE];
+        if self.options.detach {
+            // The monitor leaves the session of the terminal ruthless was started from to outlive
+            // it.
+            setsid()?;
+            detach_stdio(&self.name)?;
+        }
+        let pipes = if self.options.detach && self.pty.is_none() {
+            Some(Pipes::open(self.options.interactive)?)
+        } else {
+            None
+        };
+        let seccomp_filter = self.options.seccomp.compile(&self.options.capabilities)?;
+        let cgroup = cgroup_factory.build()?;
+        self.state.save()?;
+        let (parent_read, child_write) = pipe2(OFlag::O_CLOEXEC)?;
+        let (child_read, parent_write) = pipe2(OFlag::O_CLOEXEC)?;
+        let network = self.options.network;
+        let pid = clone(
+            Box::new(|| {
+                report_failure((|| {
+                    close(parent_read)?;
+                    close(parent_write)?;
+                    if let Some(pty) = self.pty {
+                        pty.close_master()?;
+                    }
+                    if let Some(pipes) = pipes {
+                        pipes.close_monitor_ends()?;
+                    }
+                    if let Some(ready) = self.monitor_ready {
+                        close(ready)?;
+                    }
+                    cgroup.add_pid(getpid().as_raw() as u32)?;
+                    notify(child_write)?;
+                    wait_for(child_read)?;
+                    become_root(self.id_map)?;
+                    if network.isolated() {
+                        bring_up_loopback()?;
+                    }
+                    make_mounts_private()?;
+                    let mounts = mount_filesystems(image, &self.options)?;
+                    let _mounts = switch_root(image, mounts)?;
+                    run(
+                        args,
+                        &self.options,
+                        self.id_map,
+                        &seccomp_filter,
+                        self.pty,
+                        pipes,
+                    )
+                })())
+            }),
+            stack.as_mut(),
+            jail_clone_flags(network),
+            Some(SIGCHLD as i32),
+        )?;
+        close(child_write)?;
+        close(child_read)?;
+        if let Some(pipes) = pipes {
+            pipes.close_payload_ends()?;
+        }
+        if let Some(pty) = self.pty {
+            pty.close_slave()?;
+            if !self.options.detach {
+                pty.close_master()?;
+            }
+        }
+        wait_for(parent_read)?;
+        self.id_map.write(pid)?;
+        let _network = network.attach(pid)?;
+        let _port_forwarder = PortForwarder::new(pid, &self.options.publish)?;
+        let monitor = match self.monitor_ready {
+            Some(ready) => Some((
+                Monitor::new(
+                    &self.name,
+                    pipes,
+                    self.pty,
+                    self.options.interactive,
+                    &self.options.log,
+                )?,
+                ready,
+            )),
+            None => None,
+        };
+        notify(parent_write)?;
+        update_own_state(&self.name, &self.state.id, |s| s.start(pid))?;
+        match monitor {
+            Some((mut monitor, ready)) => {
+                notify(ready)?;
+                monitor.run(pid)
+            }
+            None => wait_exit_code(pid),
+        }
+    }
 }
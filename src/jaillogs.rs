@@ -1,26 +1,528 @@
+use crate::state::container_state_path;
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
 use failure::Error;
-use std::fs::{create_dir, File};
+use serde::{Deserialize, Serialize};
+use serde_json::{from_str, to_vec};
+use std::fs::{create_dir_all, metadata, read_dir, remove_file, rename, write, File};
+use std::io::{stderr, stdout, BufRead, BufReader, Write};
+use std::mem::take;
+use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::thread::sleep;
+use std::time::Duration as StdDuration;
 
-pub(crate) const LOGS_PATH: &str = "/tmp/ruthless";
+const STDOUT_FILE: &str = "stdout";
+const STDERR_FILE: &str = "stderr";
+const JSON_LOG_FILE: &str = "json.log";
+const EXIT_CODE_FILE: &str = "exit_code";
+const MONITOR_LOG_FILE: &str = "monitor.log";
+// Longer lines are split, so that a container that never writes a newline can't grow the
+// monitor without bound.
+const MAX_LINE_SIZE: usize = 16384;
+const DEFAULT_MAX_FILE: usize = 1;
+const FOLLOW_INTERVAL: StdDuration = StdDuration::from_millis(200);
 
+#[derive(Debug, Fail)]
+pub(crate) enum LogsError {
+    #[fail(display = "Container {} has no logs", 0)]
+    NoLogs(String),
+    #[fail(display = "Invalid log driver {}", 0)]
+    InvalidLogDriver(String),
+}
+
+#[derive(Clone, Copy, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Stream {
+    Stdout,
+    Stderr,
+}
+
+// text keeps a file per stream with a timestamp before every line, json-file a single file of JSON
+// records for both streams that can be rotated.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub(crate) enum LogDriver {
+    #[default]
+    Text,
+    JsonFile,
+}
+
+impl FromStr for LogDriver {
+    type Err = LogsError;
+
+    fn from_str(s: &str) -> Result<LogDriver, Self::Err> {
+        match s {
+            "text" => Ok(LogDriver::Text),
+            "json-file" => Ok(LogDriver::JsonFile),
+            d => Err(LogsError::InvalidLogDriver(d.to_owned())),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub(crate) struct LogConfig {
+    pub(crate) driver: LogDriver,
+    pub(crate) max_size: Option<u64>,
+    pub(crate) max_file: Option<usize>,
+}
+
+struct LogRecord {
+    time: DateTime<Utc>,
+    stream: Stream,
+    log: String,
+}
+
+// A line of the json-file driver. Like in the one of Docker, [log] keeps the newline that ended
+// it.
+#[derive(Deserialize, Serialize)]
+struct JsonRecord {
+    time: String,
+    stream: Stream,
+    log: String,
+}
+
+#[derive(Default)]
+pub(crate) struct LogsOptions {
+    pub(crate) follow: bool,
+    pub(crate) since: Option<DateTime<Utc>>,
+    pub(crate) tail: Option<usize>,
+    pub(crate) timestamps: bool,
+}
+
+#[inline]
+fn format_time(time: &DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Nanos, true)
+}
+
+#[inline]
+fn parse_time(time: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(time)
+        .ok()
+        .map(|t| t.with_timezone(&Utc))
+}
+
+// Either an RFC 3339 timestamp or how long ago in seconds, minutes or hours, like 10m.
+pub(crate) fn parse_since(since: &str) -> Option<DateTime<Utc>> {
+    if let Some(time) = parse_time(since) {
+        return Some(time);
+    }
+    let split = since
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(since.len());
+    let amount = i64::from_str(&since[..split]).ok()?;
+    let unit = match &since[split..] {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        _ => return None,
+    };
+    // Durations out of range are invalid rather than a panic.
+    let milliseconds = amount.checked_mul(unit)?.checked_mul(1000)?;
+    Utc::now().checked_sub_signed(Duration::milliseconds(milliseconds))
+}
+
+// json.log is the current file and json.log.[index] the rotated ones, the oldest having the
+// highest index.
+fn json_log_path(folder: &Path, index: usize) -> PathBuf {
+    match index {
+        0 => folder.join(JSON_LOG_FILE),
+        i => folder.join(format!("{}.{}", JSON_LOG_FILE, i)),
+    }
+}
+
+// Rotated when the next record would make it bigger than [max_size], keeping [max_file] files
+// including the current one.
+struct JsonLog {
+    folder: PathBuf,
+    file: File,
+    size: u64,
+    max_size: Option<u64>,
+    max_file: usize,
+}
+
+impl JsonLog {
+    fn create(folder: &Path, config: &LogConfig) -> Result<JsonLog, Error> {
+        Ok(JsonLog {
+            folder: folder.to_path_buf(),
+            file: File::create(json_log_path(folder, 0))?,
+            size: 0,
+            max_size: config.max_size,
+            max_file: config.max_file.unwrap_or(DEFAULT_MAX_FILE),
+        })
+    }
+
+    fn write(&mut self, record: &[u8]) -> Result<(), Error> {
+        let length = record.len() as u64;
+        if self
+            .max_size
+            .is_some_and(|max| self.size > 0 && self.size + length > max)
+        {
+            self.rotate()?;
+        }
+        self.file.write_all(record)?;
+        self.size += length;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> Result<(), Error> {
+        for i in (1..self.max_file).rev() {
+            let path = json_log_path(&self.folder, i - 1);
+            if path.exists() {
+                rename(path, json_log_path(&self.folder, i))?;
+            }
+        }
+        // With a single file it's still there. It's replaced instead of truncated, so that
+        // readers following it see a new inode and start over.
+        let current = json_log_path(&self.folder, 0);
+        if current.exists() {
+            remove_file(&current)?;
+        }
+        self.file = File::create(current)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+enum LogOutput {
+    Text { stdout: File, stderr: File },
+    JsonFile(JsonLog),
+}
+
+// Splits the output of a container in lines, each recorded with the time it was written at.
+pub(crate) struct Logger {
+    output: LogOutput,
+    stdout_line: Vec<u8>,
+    stderr_line: Vec<u8>,
+}
+
+impl Logger {
+    pub(crate) fn write(&mut self, stream: Stream, mut data: &[u8]) -> Result<(), Error> {
+        while let Some(i) = data.iter().position(|b| *b == b'\n') {
+            self.line(stream).extend_from_slice(&data[..=i]);
+            self.write_line(stream)?;
+            data = &data[i + 1..];
+        }
+        self.line(stream).extend_from_slice(data);
+        if self.line(stream).len() >= MAX_LINE_SIZE {
+            self.write_line(stream)?;
+        }
+        Ok(())
+    }
+
+    // Writes what's left of unfinished lines.
+    pub(crate) fn flush(&mut self) -> Result<(), Error> {
+        for stream in [Stream::Stdout, Stream::Stderr].iter() {
+            if !self.line(*stream).is_empty() {
+                self.write_line(*stream)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn line(&mut self, stream: Stream) -> &mut Vec<u8> {
+        match stream {
+            Stream::Stdout => &mut self.stdout_line,
+            Stream::Stderr => &mut self.stderr_line,
+        }
+    }
+
+    fn write_line(&mut self, stream: Stream) -> Result<(), Error> {
+        let mut line = take(self.line(stream));
+        let time = format_time(&Utc::now());
+        match &mut self.output {
+            LogOutput::Text { stdout, stderr } => {
+                let mut record = format!("{} ", time).into_bytes();
+                record.append(&mut line);
+                if record.last() != Some(&b'\n') {
+                    record.push(b'\n');
+                }
+                match stream {
+                    Stream::Stdout => stdout.write_all(&record)?,
+                    Stream::Stderr => stderr.write_all(&record)?,
+                }
+            }
+            LogOutput::JsonFile(log) => {
+                let mut record = to_vec(&JsonRecord {
+                    time,
+                    stream,
+                    log: String::from_utf8_lossy(&line).into_owned(),
+                })?;
+                record.push(b'\n');
+                log.write(&record)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy)]
+enum LogFormat {
+    Text(Stream),
+    Json,
+}
+
+struct LogReader {
+    path: PathBuf,
+    file: BufReader<File>,
+    format: LogFormat,
+    pending: Vec<u8>,
+}
+
+impl LogReader {
+    fn open(path: PathBuf, format: LogFormat) -> Result<LogReader, Error> {
+        Ok(LogReader {
+            file: BufReader::new(File::open(&path)?),
+            path,
+            format,
+            pending: vec![],
+        })
+    }
+
+    // The records written since the last read. An unfinished line is kept until the rest of it
+    // arrives. When the file was rotated, the new one is read after the end of the old one.
+    fn read(&mut self) -> Result<Vec<LogRecord>, Error> {
+        let mut records = vec![];
+        loop {
+            while self.file.read_until(b'\n', &mut self.pending)? > 0 {
+                if self.pending.last() != Some(&b'\n') {
+                    break;
+                }
+                let line = take(&mut self.pending);
+                let line = String::from_utf8_lossy(&line[..line.len() - 1]);
+                if let Some(record) = self.parse(&line) {
+                    records.push(record);
+                }
+            }
+            if !self.rotated()? {
+                return Ok(records);
+            }
+            self.file = BufReader::new(File::open(&self.path)?);
+            self.pending.clear();
+        }
+    }
+
+    fn rotated(&self) -> Result<bool, Error> {
+        Ok(match metadata(&self.path) {
+            Ok(m) => m.ino() != self.file.get_ref().metadata()?.ino(),
+            Err(_) => false,
+        })
+    }
+
+    fn parse(&self, line: &str) -> Option<LogRecord> {
+        match self.format {
+            LogFormat::Text(stream) => {
+                let i = line.find(' ')?;
+                Some(LogRecord {
+                    time: parse_time(&line[..i])?,
+                    stream,
+                    log: line[i + 1..].to_owned(),
+                })
+            }
+            LogFormat::Json => {
+                let record = from_str::<JsonRecord>(line).ok()?;
+                Some(LogRecord {
+                    time: parse_time(&record.time)?,
+                    stream: record.stream,
+                    log: record
+                        .log
+                        .strip_suffix('\n')
+                        .unwrap_or(&record.log)
+                        .to_owned(),
+                })
+            }
+        }
+    }
+}
+
+// Records of both streams are merged by time. The sort is stable, so records of the same time keep
+// the order they were written in.
+fn read_records(
+    readers: &mut [LogReader],
+    since: Option<DateTime<Utc>>,
+) -> Result<Vec<LogRecord>, Error> {
+    let mut records = vec![];
+    for reader in readers.iter_mut() {
+        records.append(&mut reader.read()?);
+    }
+    records.sort_by_key(|r| r.time);
+    records.retain(|r| since.is_none_or(|s| r.time >= s));
+    Ok(records)
+}
+
+fn print_records(records: &[LogRecord], timestamps: bool) -> Result<(), Error> {
+    let stdout = stdout();
+    let stderr = stderr();
+    let mut stdout = stdout.lock();
+    let mut stderr = stderr.lock();
+    for record in records {
+        let output: &mut dyn Write = match record.stream {
+            Stream::Stdout => &mut stdout,
+            Stream::Stderr => &mut stderr,
+        };
+        if timestamps {
+            write!(output, "{} ", format_time(&record.time))?;
+        }
+        writeln!(output, "{}", record.log)?;
+    }
+    stdout.flush()?;
+    Ok(())
+}
+
+// Where the monitor of a detached container and the helpers it starts write, instead of the terminal
+// ruthless was started from.
+pub(crate) fn monitor_log_path(container: &str) -> Result<PathBuf, Error> {
+    let folder = container_state_path(container)?;
+    create_dir_all(&folder)?;
+    Ok(folder.join(MONITOR_LOG_FILE))
+}
+
+// The logs of a detached container, kept in its state folder on the host.
 pub(crate) struct JailLogs {
     folder: PathBuf,
 }
 
 impl JailLogs {
-    pub(crate) fn new() -> Result<JailLogs, Error> {
-        let folder = Path::new(LOGS_PATH).to_path_buf();
-        create_dir(&folder)?;
+    // Starts the logs of a new run of [container], dropping the ones of the previous run, which
+    // could have used another driver.
+    pub(crate) fn create(container: &str) -> Result<JailLogs, Error> {
+        let folder = container_state_path(container)?;
+        create_dir_all(&folder)?;
+        for entry in read_dir(&folder)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if [STDOUT_FILE, STDERR_FILE, EXIT_CODE_FILE].contains(&name.as_str())
+                || name.starts_with(JSON_LOG_FILE)
+            {
+                remove_file(entry.path())?;
+            }
+        }
         Ok(JailLogs { folder })
     }
-    pub(crate) fn stdin(&self) -> Result<File, Error> {
-        Ok(File::create(self.folder.join("stdin"))?)
+
+    pub(crate) fn open(container: &str) -> Result<JailLogs, Error> {
+        let folder = container_state_path(container)?;
+        if !folder.join(STDOUT_FILE).exists() && !json_log_path(&folder, 0).exists() {
+            Err(LogsError::NoLogs(container.to_owned()))?
+        }
+        Ok(JailLogs { folder })
     }
-    pub(crate) fn stdout(&self) -> Result<File, Error> {
-        Ok(File::create(self.folder.join("stdout"))?)
+
+    pub(crate) fn logger(&self, config: &LogConfig) -> Result<Logger, Error> {
+        let output = match config.driver {
+            LogDriver::Text => LogOutput::Text {
+                stdout: File::create(self.folder.join(STDOUT_FILE))?,
+                stderr: File::create(self.folder.join(STDERR_FILE))?,
+            },
+            LogDriver::JsonFile => LogOutput::JsonFile(JsonLog::create(&self.folder, config)?),
+        };
+        Ok(Logger {
+            output,
+            stdout_line: vec![],
+            stderr_line: vec![],
+        })
     }
-    pub(crate) fn stderr(&self) -> Result<File, Error> {
-        Ok(File::create(self.folder.join("stderr"))?)
+
+    pub(crate) fn record_exit_code(&self, exit_code: isize) -> Result<(), Error> {
+        write(self.folder.join(EXIT_CODE_FILE), format!("{}\n", exit_code))?;
+        Ok(())
+    }
+
+    // The records of rotated json-file logs, which are read whole from the oldest, and the readers
+    // of the files that are still written.
+    fn readers(&self) -> Result<(Vec<LogRecord>, Vec<LogReader>), Error> {
+        if !json_log_path(&self.folder, 0).exists() {
+            let readers = vec![
+                LogReader::open(
+                    self.folder.join(STDOUT_FILE),
+                    LogFormat::Text(Stream::Stdout),
+                )?,
+                LogReader::open(
+                    self.folder.join(STDERR_FILE),
+                    LogFormat::Text(Stream::Stderr),
+                )?,
+            ];
+            return Ok((vec![], readers));
+        }
+        let rotated = (1..)
+            .take_while(|i| json_log_path(&self.folder, *i).exists())
+            .count();
+        let mut records = vec![];
+        for i in (1..=rotated).rev() {
+            let mut reader = LogReader::open(json_log_path(&self.folder, i), LogFormat::Json)?;
+            records.append(&mut reader.read()?);
+        }
+        let reader = LogReader::open(json_log_path(&self.folder, 0), LogFormat::Json)?;
+        Ok((records, vec![reader]))
+    }
+
+    // With [follow] the logs are polled for new records until the exit code of the container is
+    // recorded.
+    pub(crate) fn show(&self, options: &LogsOptions) -> Result<(), Error> {
+        let (mut records, mut readers) = self.readers()?;
+        records.append(&mut read_records(&mut readers, options.since)?);
+        records.retain(|r| options.since.is_none_or(|s| r.time >= s));
+        if let Some(tail) = options.tail {
+            records.drain(..records.len().saturating_sub(tail));
+        }
+        print_records(&records, options.timestamps)?;
+        if !options.follow {
+            return Ok(());
+        }
+        loop {
+            // Checked before reading, so that the last records of the container aren't missed.
+            let finished = self.folder.join(EXIT_CODE_FILE).exists();
+            print_records(
+                &read_records(&mut readers, options.since)?,
+                options.timestamps,
+            )?;
+            if finished {
+                return Ok(());
+            }
+            sleep(FOLLOW_INTERVAL);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn parses_since() {
+        assert_eq!(
+            parse_since("2020-01-02T15:04:05Z").unwrap(),
+            Utc.ymd(2020, 1, 2).and_hms(15, 4, 5)
+        );
+        assert_eq!(
+            parse_since("2020-01-02T16:04:05+01:00").unwrap(),
+            Utc.ymd(2020, 1, 2).and_hms(15, 4, 5)
+        );
+        for (since, seconds) in [("30", 30), ("30s", 30), ("10m", 600), ("2h", 7200)].iter() {
+            let ago = Utc::now() - parse_since(since).unwrap();
+            assert!(
+                (ago - Duration::seconds(*seconds)).num_seconds().abs() <= 1,
+                "{}",
+                since
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_since() {
+        for since in [
+            "",
+            "m",
+            "10d",
+            "-10m",
+            "1.5h",
+            "10 m",
+            "2020-01-02",
+            "9223372036854775807h",
+        ]
+        .iter()
+        {
+            assert!(parse_since(since).is_none(), "{}", since);
+        }
     }
 }
@@ -11,50 +11,246 @@ use uuid::Uuid;
 
 mod args;
 mod btrfs_send;
+mod capabilities;
 mod cgroup;
+mod exec;
+mod idmap;
 mod images;
+mod init;
 mod jail;
 mod jaillogs;
+mod monitor;
 mod mount;
+mod network;
 mod oci_image;
+mod seccomp;
+mod state;
+mod stats;
+mod syscalls;
+mod tty;
+mod ulimit;
+mod user;
 
-use crate::cgroup::{get_active_cgroups, terminate_cgroup_processes};
+use crate::cgroup::{
+    freeze_container, get_active_cgroups, kill_cgroup_processes, signal_cgroup_processes,
+    update_cgroup, wait_until_empty,
+};
+use crate::exec::{exec, find_main_process, ExecError};
+use crate::jaillogs::{JailLogs, LogsOptions};
+use crate::monitor::attach;
 use crate::oci_image::{export, OCIImage};
+use crate::state::{
+    delete_container_state, get_container_states, print_container_states, update_state,
+    ContainerState, ListOptions, StateError, Status,
+};
+use crate::stats::{show_stats, StatsOptions};
 use args::Command;
 use cgroup::{CgroupFactory, CgroupOptions};
 use images::ImageRepository;
-use jail::Jail;
-use std::fs::read_to_string;
+use jail::{Jail, JailOptions};
+use nix::errno::Errno;
+use nix::sys::signal::{kill, Signal};
+use nix::Error as SyscallError;
+use std::time::Duration;
 
 const USAGE: &str = "Ruthless is a small application to run rootless, daemonless containers.
 
 Possible commands:
-ruthless run [image] [command] # Run the given command on the image.
-ruthless logs [container] # Show logs of a container
-ruthless container delete [container] # Kill running containers
-ruthless container list # List all running containers
+ruthless run [image] [command] # Run the given command (or the image's default one) on the image.
+ruthless attach [container] # Attach to the standard streams of a detached container
+ruthless exec [container] [command] # Run an additional command inside a running container
+ruthless logs [container] # Show logs of a detached container
+ruthless container delete [container] # Kill a container and delete its state
+ruthless container kill [container] # Send a signal to a running container
+ruthless container list # List containers
+ruthless container pause [container] # Suspend every process of a running container
+ruthless container unpause [container] # Resume a paused container
+ruthless container stats [container] # Show the resource usage of running containers
+ruthless container stop [container] # Stop a running container
+ruthless container update [container] # Change the resource limits of a running container
 ruthless image list # List images in the system
 ruthless image delete [image] # Deletes image [image]
 ruthless export [image] [tarball] # Export [image] into the location [tarball]
 ruthless import [tarball] # Import a OCI compatible tarball into the image repository
 ruthless help # See this message
 ruthless help [command] # Describe what an specific command does";
+const USAGE_ATTACH: &str = "Usage: ruthless attach [options] [container]
+
+Attach to the standard streams of the detached container [container]. Its output is streamed to the
+one of ruthless and, when it was started with --interactive, the standard input of ruthless is sent
+to it. If it has a pseudo-terminal the terminal of ruthless is put in raw mode. When the standard
+input of ruthless ends, the one of the container is closed, or gets an end of transmission with a
+pseudo-terminal. ruthless exits with the exit code of the container when it finishes, or with 0
+after detaching. Losing the connection to the monitor is an error.
+
+Options:
+
+--detach-keys=[keys]
+\tDetach from the container, leaving it running, when the sequence [keys] is typed. It's a comma
+separated list of characters and ctrl-[key] combinations. Defaults to ctrl-p,ctrl-q.";
 const USAGE_CONTAINER_DELETE: &str = "Usage: ruthless container delete [container]
 
-Attempts to delete a running container by killing the processes running in it.";
-const USAGE_CONTAINER_LIST: &str = "Usage: ruthless container list
+Attempts to delete a container by killing the processes running in it, and deletes its state and
+logs.";
+const USAGE_CONTAINER_KILL: &str = "Usage: ruthless container kill [options] [container]
+
+Send a signal to the main process of the running container [container], the command it was started
+with.
+
+Options:
+
+-a, --all
+\tSend the signal to every process of the container instead, including the ones started with
+ruthless exec.
+-s [signal], --signal [signal], --signal=[signal]
+\tThe signal to send, as a number or a name like HUP or SIGHUP. Defaults to SIGKILL.";
+const USAGE_CONTAINER_LIST: &str = "Usage: ruthless container list [options]
+
+List the containers that are currently running in the system, newest first. Every container keeps a
+state.json record under ~/.local/lib/ruthless/containers/[container] with its id, name, image,
+command, options, PID, status, creation, start and finish times and exit code.
+
+Options:
+
+-a, --all
+\tAlso list the containers that were created or have exited.
+--filter [key]=[value], --filter=[key]=[value]
+\tOnly list the containers whose id starts with [value] (id), whose name contains it (name), that
+run the image [value] (image) or whose status is [value], one of created, running or exited
+(status). Filters of the same key match when any of them does. Can be repeated.
+--format [table|json], --format=[table|json]
+\tPrint a table (the default) or a JSON record per line.";
+const USAGE_CONTAINER_PAUSE: &str = "Usage: ruthless container pause [container]
+
+Suspend the processes of the running container [container] with the cgroup freezer until it's
+unpaused. Its status is paused in the meantime.";
+const USAGE_CONTAINER_UNPAUSE: &str = "Usage: ruthless container unpause [container]
+
+Resume the processes of the paused container [container].";
+const USAGE_CONTAINER_STATS: &str = "Usage: ruthless container stats [options] [container...]
+
+Show the resource usage of the given running containers, or of every running container when none is
+given, refreshed every second. It's read from the cgroup of every container: the CPU usage as a
+percentage of a single CPU, the memory usage without the reclaimable page cache along with its limit
+and peak, the bytes read and written by block devices in total and per second, and the number of
+processes.
+
+Options:
+
+--no-stream
+\tPrint a single snapshot and exit.
+--format [table|json], --format=[table|json]
+\tPrint a table (the default) or a JSON record per container and sample.";
+const USAGE_CONTAINER_STOP: &str = "Usage: ruthless container stop [options] [container]
+
+Stop the running container [container] by sending a signal to its main process and waiting for
+every process of the container to finish. The ones still running after the timeout are killed
+through the cgroup of the container.
 
-List all the containers that are currently running in the system";
+Options:
+
+-s [signal], --signal [signal], --signal=[signal]
+\tThe signal to send, as a number or a name like TERM or SIGTERM. Defaults to SIGTERM.
+-t [seconds], --time [seconds], --time=[seconds]
+\tThe seconds to wait before killing the processes of the container. Defaults to 10.";
+const USAGE_CONTAINER_UPDATE: &str = "Usage: ruthless container update [options] [container]
+
+Change the resource limits of the running container [container]. It takes the same resource options
+as ruthless run, from --cpu-max to --rdma-max, and at least one of them is required. The new values
+are recorded in the state of the container.";
+const USAGE_EXEC: &str = "Usage: ruthless exec [options] [container] [command]
+
+Run [command] inside the running container [container]. It joins the namespaces of the container,
+runs in a cgroup next to the one of the container and gets the environment of the command the
+container was started with.
+
+Options:
+
+-i, --interactive
+\tKeep the standard input of [command] attached to the one of ruthless. Otherwise it reads from
+/dev/null.
+-t, --tty
+\tRun [command] in a pseudo-terminal.";
 const USAGE_RUN: &str = "Usage: ruthless run [options] [image] [command]
 
-Run a container with the process [command] over the file system [image]
+Run a container with the process [command] over the file system [image]. When [command] is omitted
+the command defined by the image is used. ruthless exits with the exit code of [command], or 128
+plus the number of the signal that killed it. Detached containers record it in the exit_code file
+next to their logs. The environment, working directory and user of the container are taken from the
+image configuration. The container gets its own /proc, a minimal /dev with the null, zero, full,
+random, urandom and tty devices of the host, /dev/pts, /dev/shm, /dev/mqueue and a read-only /sys.
+Paths of /proc and /sys that expose or reconfigure the host, like /proc/kcore, /proc/sys,
+/proc/sysrq-trigger or /sys/firmware, are masked or read-only.
 
 Options:
 
 -d, --detach
-\tDetach the process container and run it in the background.
+\tDetach the process container and run it in the background. A monitor process keeps its standard
+streams, writes its output to the logs and lets ruthless attach reconnect to them. The monitor and
+its helpers write their own errors to the monitor.log file next to the logs.
+--log-driver=[text|json-file]
+\tSet how the logs of a detached container are written. text (the default) keeps a file per stream
+with a timestamp before every line and json-file a single json.log file with a {time, stream, log}
+JSON record per line.
+--log-opt [option], --log-opt=[option]
+\tConfigure the json-file log driver. max-size=[size] rotates json.log when it would grow past
+[size], which can have a k, m or g suffix, and max-file=[count] sets how many files are kept,
+including the current one. Can be repeated.
+-i, --interactive
+\tKeep the standard input of [command] attached to the one of ruthless. Otherwise it reads from
+/dev/null.
+-t, --tty
+\tRun [command] in a pseudo-terminal. With --interactive the terminal of ruthless is put in raw mode
+and its window size changes are propagated to the container. With --detach the pseudo-terminal is
+kept by the monitor of the container.
+--init
+\tKeep a minimal init as PID 1 of the container that forwards signals to the process group of
+[command], reaps orphaned processes and exits with the status of [command].
 -n [name], --name=[name]
 \tRun the container with a specific name.
+--network [none|host|slirp], --network=[none|host|slirp]
+\tSet the networking mode of the container. host (the default) shares the network namespace of the
+host, none creates an isolated network namespace with only a loopback interface and slirp also
+attaches a rootless user-mode network stack through slirp4netns to give outbound connectivity.
+-p [host port]:[container port][/protocol], --publish [host port]:[container port][/protocol],
+--publish=[host port]:[container port][/protocol]
+\tForward connections to [host port] on the host into [container port] inside the container. The
+protocol can be tcp (the default) or udp. Requires a network mode other than host.
+--cap-add=[capability]
+\tAdd [capability] (for example NET_ADMIN or CAP_NET_ADMIN) to the default set of capabilities of
+the container. ALL adds every capability.
+--cap-drop=[capability]
+\tDrop [capability] from the default set of capabilities of the container. ALL drops every
+capability.
+--privileged
+\tRun the container with the full set of capabilities, without seccomp filtering and without
+masking the sensitive paths of /proc and /sys.
+--read-only
+\tMount the root file system of the container as read-only. Volumes, tmpfs mounts and /dev stay
+writable.
+--entrypoint=[entrypoint]
+\tOverride the entrypoint of the image. An empty value clears it.
+-e [name]=[value], --env=[name]=[value]
+\tSet the environment variable [name] inside the container. When only [name] is given its value is
+taken from the environment of ruthless.
+--env-file=[file]
+\tRead environment variables from [file], one [name]=[value] per line. Empty lines and lines
+starting with # are ignored.
+-w [path], --workdir=[path]
+\tRun the command in the absolute [path] of the container, creating it if needed.
+-v [source]:[destination][:ro], --volume=[source]:[destination][:ro]
+\tBind mount the absolute host path [source] on [destination] inside the container, read-only
+when ro is given. Missing destinations are created in the image.
+--mount=type=bind,src=[source],dst=[destination][,ro]
+\tLong form of --volume.
+--tmpfs=[destination][:options]
+\tMount a tmpfs on [destination] with the comma separated mount [options] (for example
+size=64m,mode=1777,exec). It is mounted nosuid, nodev and noexec unless told otherwise.
+--shm-size=[size]
+\tSet the size of /dev/shm, in bytes or with a k, m or g suffix. Defaults to 64m.
+-u [user][:group], --user=[user][:group]
+\tRun the command as [user] (a name or a uid) and optionally [group] (a name or a gid), resolved
+through the /etc/passwd and /etc/group files of the image.
 --cpu-max=[cpu max]
 \tSet the value to the interface cpu.max.
 --cpu-weight=[cpu weight]
@@ -88,7 +284,17 @@ in between quotes.
 \tSet the value to the interface pids.max.
 --rdma-max=[cpuset cpus partition]
 \tSet the value to the interface rdma.max. This value requires spaces, so you should put the argument
-in between quotes.";
+in between quotes.
+--security-opt seccomp=[profile], --security-opt=seccomp=[profile]
+\tFilter the system calls of the container with the Docker/OCI seccomp JSON file [profile] instead
+of the default profile, which blocks system calls like keyctl and kexec_load, and the ones like
+mount, setns or ptrace that need a capability the container doesn't have, like SYS_ADMIN or
+SYS_PTRACE. seccomp=unconfined disables the filtering. Filtering is only supported on x86_64,
+elsewhere the default profile is skipped with a warning.
+--ulimit [name]=[soft limit][:[hard limit]], --ulimit=[name]=[soft limit][:[hard limit]]
+\tSet the resource limit [name] of the container (as, core, cpu, data, fsize, locks, memlock,
+msgqueue, nice, nofile, nproc, rss, rtprio, rttime, sigpending or stack). Limits can be unlimited.
+When the hard limit is omitted it takes the value of the soft limit. Can be repeated.";
 const USAGE_IMAGE_LIST: &str = "Usage: ruthless image list
 
 List all the images available right now in the repository.";
@@ -101,29 +307,118 @@ Import a OCI tarball image into the ruthless image repository.";
 const USAGE_EXPORT: &str = "Usage: ruthless export [image] [tarball]
 
 Export an image into a docker compatible tarball.";
-const USAGE_LOGS: &str = "Usage: ruthless logs [container]
+const USAGE_LOGS: &str = "Usage: ruthless logs [options] [container]
+
+Prints the standard output of the detached container [container] into the current standard output
+and its standard error into the current standard error, in the order they were written. Logs are
+kept on the host under ~/.local/lib/ruthless/containers/[container], next to the exit_code file.
 
-Prints the standard output of the container into the current standard output and the standard error
-of the container into the current standard error.";
+Options:
+
+-f, --follow
+\tKeep printing new output until the container finishes.
+-n [lines], --tail [lines], --tail=[lines]
+\tOnly print the last [lines] lines. all prints every line, which is the default.
+--since [time], --since=[time]
+\tOnly print the lines written after [time], either an RFC 3339 timestamp like
+2020-01-02T15:04:05Z or a duration before now in seconds, minutes or hours like 30s, 10m or 2h.
+-t, --timestamps
+\tPrefix every line with the time it was written at.";
 
 fn run_command(
     image: &str,
     command: &[String],
-    detach: bool,
+    entrypoint: &Option<String>,
+    mut jail_options: JailOptions,
     name: Option<String>,
+    options: &[String],
     resource_options: &[CgroupOptions],
-) -> Result<(), Error> {
-    let name = name.unwrap_or_else(|| Uuid::new_v4().to_string());
+) -> Result<isize, Error> {
+    let id = Uuid::new_v4().to_string();
+    let name = name.unwrap_or_else(|| id.clone());
+    // Like in the listing, records of containers without a cgroup are finished whatever their status.
+    if get_active_cgroups()?.contains(&name)
+        && ContainerState::load(&name)?.is_some_and(|s| s.status.is_up())
+    {
+        Err(StateError::NameInUse(name.clone()))?
+    }
     let image_repository = ImageRepository::new()?;
+    let image_config = image_repository.get_image_config(image)?;
+    let command = image_config.command(entrypoint, command);
+    jail_options.apply_image_config(&image_config);
     let image_location = image_repository.get_image_location_for_process(image, name.as_str())?;
+    let mut state = ContainerState::new(
+        id,
+        name.clone(),
+        image.to_owned(),
+        command.clone(),
+        options.to_owned(),
+    );
+    state.confinement = jail_options.confinement();
     let cgroup_factory = CgroupFactory::new(name, resource_options.to_owned());
-    let mut jail = Jail::new(detach);
-    jail.run(command, image_location.to_str().unwrap(), &cgroup_factory)?;
-    Ok(())
+    let mut jail = Jail::new(state, jail_options);
+    jail.run(&command, image_location.to_str().unwrap(), &cgroup_factory)
+}
+
+fn attach_command(container: &str, detach_keys: &[u8]) -> Result<isize, Error> {
+    attach(container, detach_keys)
 }
 
 fn delete_container_command(container: &str) -> Result<(), Error> {
-    terminate_cgroup_processes(container)
+    if get_active_cgroups()?.iter().any(|c| c == container) {
+        kill_cgroup_processes(container)?;
+    }
+    delete_container_state(container)
+}
+
+fn kill_container_command(container: &str, signal: Signal, all: bool) -> Result<(), Error> {
+    if !get_active_cgroups()?.iter().any(|c| c == container) {
+        Err(ExecError::ContainerNotRunning(container.to_owned()))?
+    }
+    if all {
+        return signal_cgroup_processes(container, signal);
+    }
+    match kill(find_main_process(container)?, signal) {
+        Ok(()) | Err(SyscallError::Sys(Errno::ESRCH)) => {}
+        Err(e) => Err(e)?,
+    }
+    Ok(())
+}
+
+fn pause_container_command(container: &str, paused: bool) -> Result<(), Error> {
+    if !get_active_cgroups()?.iter().any(|c| c == container) {
+        Err(ExecError::ContainerNotRunning(container.to_owned()))?
+    }
+    freeze_container(container, paused)?;
+    update_state(container, |s| s.pause(paused))
+}
+
+fn stop_container_command(container: &str, signal: Signal, time: Duration) -> Result<(), Error> {
+    kill_container_command(container, signal, false)?;
+    // A paused container couldn't handle the signal.
+    if ContainerState::load(container)?.is_some_and(|s| s.status == Status::Paused) {
+        pause_container_command(container, false)?;
+    }
+    if !wait_until_empty(container, Some(time))? {
+        kill_cgroup_processes(container)?;
+    }
+    Ok(())
+}
+
+fn update_container_command(
+    container: &str,
+    resource_options: &[CgroupOptions],
+    resources: &[String],
+) -> Result<(), Error> {
+    if !get_active_cgroups()?.iter().any(|c| c == container) {
+        Err(ExecError::ContainerNotRunning(container.to_owned()))?
+    }
+    update_cgroup(container, resource_options)?;
+    update_state(container, |s| s.update_resources(resources))
+}
+
+fn container_stats_command(containers: &[String], options: &StatsOptions) -> Result<(), Error> {
+    show_stats(containers, options)
 }
 
 fn delete_image_command(image: &str) -> Result<(), Error> {
@@ -132,6 +427,15 @@ fn delete_image_command(image: &str) -> Result<(), Error> {
     Ok(())
 }
 
+fn exec_command(
+    container: &str,
+    command: &[String],
+    interactive: bool,
+    tty: bool,
+) -> Result<isize, Error> {
+    exec(container, command, interactive, tty)
+}
+
 fn export_command(image: &str, tarball: &str) -> Result<(), Error> {
     let image_repository = ImageRepository::new()?;
     export(&image_repository, image, tarball)?;
@@ -144,9 +448,17 @@ fn import_command(tarball: &str) -> Result<(), Error> {
     oci_image.import(&image_repository)
 }
 
-fn list_containers_command() -> Result<(), Error> {
-    get_active_cgroups()?.iter().for_each(|c| println!("{}", c));
-    Ok(())
+fn list_containers_command(options: &ListOptions) -> Result<(), Error> {
+    let active = get_active_cgroups()?;
+    let mut states = get_container_states()?;
+    for state in states.iter_mut() {
+        // The monitor of the container went away without recording how it finished.
+        if state.status.is_up() && !active.contains(&state.name) {
+            state.status = Status::Exited;
+        }
+    }
+    states.retain(|s| options.matches(s));
+    print_container_states(&states, options.format)
 }
 
 fn list_images_command() -> Result<(), Error> {
@@ -157,12 +469,8 @@ fn list_images_command() -> Result<(), Error> {
     Ok(())
 }
 
-fn show_container_logs(container: &str) -> Result<(), Error> {
-    let image_repository = ImageRepository::new()?;
-    let logs_path = image_repository.get_logs_path(container);
-    println!("{}", read_to_string(logs_path.join("stdout"))?.trim());
-    eprintln!("{}", read_to_string(logs_path.join("stderr"))?.trim());
-    Ok(())
+fn show_container_logs(container: &str, options: &LogsOptions) -> Result<(), Error> {
+    JailLogs::open(container)?.show(options)
 }
 
 fn main() {
@@ -171,10 +479,26 @@ fn main() {
     args.next();
     let arguments: Vec<String> = args.collect();
     match Command::try_from(arguments) {
+        Ok(Command::Attach {
+            container,
+            detach_keys,
+        }) => {
+            let exit_code = attach_command(&container, &detach_keys).unwrap();
+            exit(exit_code as i32)
+        }
         Ok(Command::DeleteContainer(container)) => {
             delete_container_command(container.as_str()).unwrap()
         }
         Ok(Command::DeleteImage(image)) => delete_image_command(image.as_str()).unwrap(),
+        Ok(Command::Exec {
+            container,
+            command,
+            interactive,
+            tty,
+        }) => {
+            let exit_code = exec_command(&container, &command, interactive, tty).unwrap();
+            exit(exit_code as i32)
+        }
         Ok(Command::Export(image, tarball)) => {
             export_command(image.as_str(), tarball.as_str()).unwrap()
         }
@@ -182,10 +506,18 @@ fn main() {
             println!("{}", USAGE);
         }
         Ok(Command::Help(Some(c))) => match c.as_str() {
+            "attach" => println!("{}", USAGE_ATTACH),
+            "container kill" => println!("{}", USAGE_CONTAINER_KILL),
             "container list" => println!("{}", USAGE_CONTAINER_LIST),
+            "container pause" => println!("{}", USAGE_CONTAINER_PAUSE),
+            "container unpause" => println!("{}", USAGE_CONTAINER_UNPAUSE),
             "container delete" => println!("{}", USAGE_CONTAINER_DELETE),
+            "container stats" => println!("{}", USAGE_CONTAINER_STATS),
+            "container stop" => println!("{}", USAGE_CONTAINER_STOP),
+            "container update" => println!("{}", USAGE_CONTAINER_UPDATE),
             "image delete" => println!("{}", USAGE_IMAGE_DELETE),
             "image list" => println!("{}", USAGE_IMAGE_LIST),
+            "exec" => println!("{}", USAGE_EXEC),
             "export" => println!("{}", USAGE_EXPORT),
             "import" => println!("{}", USAGE_IMPORT),
             "logs" => println!("{}", USAGE_LOGS),
@@ -193,18 +525,57 @@ fn main() {
             _ => panic!("Invalid command.\n\n{}", USAGE),
         },
         Ok(Command::Import(tarball)) => import_command(tarball.as_str()).unwrap(),
-        Ok(Command::ListContainers) => list_containers_command().unwrap(),
+        Ok(Command::KillContainer {
+            container,
+            signal,
+            all,
+        }) => kill_container_command(&container, signal, all).unwrap(),
+        Ok(Command::ListContainers(options)) => list_containers_command(&options).unwrap(),
         Ok(Command::ListImages) => list_images_command().unwrap(),
-        Ok(Command::Logs(c)) => show_container_logs(&c).unwrap(),
+        Ok(Command::Logs { container, options }) => {
+            show_container_logs(&container, &options).unwrap()
+        }
+        Ok(Command::PauseContainer(container)) => {
+            pause_container_command(&container, true).unwrap()
+        }
         Ok(Command::Run {
             command,
-            detach,
+            entrypoint,
             image,
+            jail_options,
             name,
+            options,
             resource_options,
         }) => {
-            run_command(image.as_str(), &command, detach, name, &resource_options).unwrap();
+            let exit_code = run_command(
+                image.as_str(),
+                &command,
+                &entrypoint,
+                *jail_options,
+                name,
+                &options,
+                &resource_options,
+            )
+            .unwrap();
+            exit(exit_code as i32)
         }
+        Ok(Command::Stats {
+            containers,
+            options,
+        }) => container_stats_command(&containers, &options).unwrap(),
+        Ok(Command::StopContainer {
+            container,
+            signal,
+            time,
+        }) => stop_container_command(&container, signal, time).unwrap(),
+        Ok(Command::UnpauseContainer(container)) => {
+            pause_container_command(&container, false).unwrap()
+        }
+        Ok(Command::UpdateContainer {
+            container,
+            resource_options,
+            resources,
+        }) => update_container_command(&container, &resource_options, &resources).unwrap(),
         Err(e) => {
             eprintln!("{}", e);
             eprintln!("{}", USAGE);
@@ -0,0 +1,564 @@
+use crate::jail::SIGNAL_EXIT_CODE_BASE;
+use crate::jaillogs::{JailLogs, LogConfig, Logger, Stream};
+use crate::tty::{is_ready, window_size, write_all, Pty, RawTerminal, NO_TIMEOUT};
+use dirs::runtime_dir;
+use failure::Error;
+use nix::errno::Errno;
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+use nix::libc::{STDERR_FILENO, STDIN_FILENO, STDOUT_FILENO};
+use nix::poll::{poll, EventFlags, PollFd};
+use nix::pty::Winsize;
+use nix::sys::signal::{SigSet, Signal};
+use nix::sys::signalfd::SignalFd;
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::{close, dup, getuid, pipe2, read, write, Pid};
+use nix::Error as SyscallError;
+use std::fs::{remove_file, DirBuilder};
+use std::io::{self, ErrorKind, Read, Write};
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::Duration;
+
+const BUFFER_SIZE: usize = 4096;
+const RUNTIME_FOLDER: &str = "ruthless";
+const SOCKET_EXTENSION: &str = "sock";
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(1);
+// How long the output of a finished container is waited for before closing its clients.
+const DRAIN_TIMEOUT: i32 = 100;
+// Input of the clients that the payload didn't read yet. Beyond it, input is dropped.
+const MAX_PENDING_INPUT: usize = 65536;
+// Ends the input of a payload with a tty, as its master can't be closed on its own.
+const END_OF_TRANSMISSION: u8 = 4;
+const CONTROL_KEY_PREFIX: &str = "ctrl-";
+pub(crate) const DEFAULT_DETACH_KEYS: &str = "ctrl-p,ctrl-q";
+
+// Every message on the socket is a frame: its kind, the length of its payload as a big endian u32
+// and the payload.
+const FRAME_HEADER_SIZE: usize = 5;
+const MAX_FRAME_SIZE: usize = 65536;
+const FRAME_STDIN: u8 = 0;
+const FRAME_STDOUT: u8 = 1;
+const FRAME_STDERR: u8 = 2;
+// Sent by the monitor on connection when the container has a tty.
+const FRAME_TTY: u8 = 3;
+// Rows and columns as big endian u16.
+const FRAME_RESIZE: u8 = 4;
+// The exit code as a big endian i32.
+const FRAME_EXIT: u8 = 5;
+// Sent by a client when its standard input is closed.
+const FRAME_CLOSE_STDIN: u8 = 6;
+
+#[derive(Debug, Fail)]
+pub(crate) enum MonitorError {
+    #[fail(display = "Container {} isn't running detached", 0)]
+    ContainerNotAttachable(String),
+    #[fail(display = "Lost the connection to container {}", 0)]
+    ConnectionLost(String),
+}
+
+fn runtime_folder() -> PathBuf {
+    runtime_dir()
+        .map(|d| d.join(RUNTIME_FOLDER))
+        .unwrap_or_else(|| PathBuf::from(format!("/tmp/{}-{}", RUNTIME_FOLDER, getuid())))
+}
+
+fn socket_path(container: &str) -> PathBuf {
+    runtime_folder().join(format!("{}.{}", container, SOCKET_EXTENSION))
+}
+
+fn write_frame<W: Write>(stream: &mut W, kind: u8, payload: &[u8]) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(FRAME_HEADER_SIZE + payload.len());
+    frame.push(kind);
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(payload);
+    stream.write_all(&frame)
+}
+
+// None when the other end closed the connection.
+fn read_frame<R: Read>(stream: &mut R) -> io::Result<Option<(u8, Vec<u8>)>> {
+    let mut header = [0; FRAME_HEADER_SIZE];
+    match stream.read_exact(&mut header) {
+        Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        result => result?,
+    }
+    let length = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+    if length > MAX_FRAME_SIZE {
+        return Err(io::Error::from(ErrorKind::InvalidData));
+    }
+    let mut payload = vec![0; length];
+    stream.read_exact(&mut payload)?;
+    Ok(Some((header[0], payload)))
+}
+
+fn poll_ready(fds: &mut [PollFd], timeout: i32) -> Result<bool, Error> {
+    match poll(fds, timeout) {
+        Err(SyscallError::Sys(Errno::EINTR)) => Ok(false),
+        result => Ok(result? > 0),
+    }
+}
+
+fn try_wait(pid: Pid) -> Result<Option<isize>, Error> {
+    Ok(match waitpid(pid, Some(WaitPidFlag::WNOHANG))? {
+        WaitStatus::Exited(_, code) => Some(code as isize),
+        WaitStatus::Signaled(_, signal, _) => Some(SIGNAL_EXIT_CODE_BASE + signal as isize),
+        _ => None,
+    })
+}
+
+fn parse_key(key: &str) -> Option<u8> {
+    match key.strip_prefix(CONTROL_KEY_PREFIX) {
+        Some(control) => match control.as_bytes() {
+            [b @ b'a'..=b'z'] => Some(b - b'a' + 1),
+            [b @ b'@'..=b'_'] => Some(b - b'@'),
+            _ => None,
+        },
+        None => match key.as_bytes() {
+            [b] if b.is_ascii() => Some(*b),
+            _ => None,
+        },
+    }
+}
+
+// Detach keys are a comma separated sequence of single characters and ctrl-[key] combinations,
+// like ctrl-p,ctrl-q.
+pub(crate) fn parse_detach_keys(keys: &str) -> Option<Vec<u8>> {
+    keys.split(',').map(parse_key).collect()
+}
+
+// Pipes between a detached payload and its monitor. The payload only gets a standard input when
+// it's interactive.
+#[derive(Clone, Copy)]
+pub(crate) struct Pipes {
+    stdin: Option<(RawFd, RawFd)>,
+    stdout: (RawFd, RawFd),
+    stderr: (RawFd, RawFd),
+}
+
+impl Pipes {
+    pub(crate) fn open(interactive: bool) -> Result<Pipes, Error> {
+        let stdin = if interactive {
+            Some(pipe2(OFlag::O_CLOEXEC)?)
+        } else {
+            None
+        };
+        Ok(Pipes {
+            stdin,
+            stdout: pipe2(OFlag::O_CLOEXEC)?,
+            stderr: pipe2(OFlag::O_CLOEXEC)?,
+        })
+    }
+
+    pub(crate) fn close_monitor_ends(&self) -> Result<(), Error> {
+        if let Some((_, write)) = self.stdin {
+            close(write)?;
+        }
+        close(self.stdout.0)?;
+        close(self.stderr.0)?;
+        Ok(())
+    }
+
+    pub(crate) fn close_payload_ends(&self) -> Result<(), Error> {
+        if let Some((read, _)) = self.stdin {
+            close(read)?;
+        }
+        close(self.stdout.1)?;
+        close(self.stderr.1)?;
+        Ok(())
+    }
+
+    pub(crate) fn payload_stdio(&self) -> Result<(Stdio, Stdio, Stdio), Error> {
+        let stdin = match self.stdin {
+            Some((read, _)) => unsafe { Stdio::from_raw_fd(dup(read)?) },
+            None => Stdio::null(),
+        };
+        Ok((
+            stdin,
+            unsafe { Stdio::from_raw_fd(dup(self.stdout.1)?) },
+            unsafe { Stdio::from_raw_fd(dup(self.stderr.1)?) },
+        ))
+    }
+}
+
+// Runs next to a detached container for as long as it does. It owns the standard streams of the
+// payload, either as pipes or as the master of its pty, writes its output to the logs and serves
+// it to the clients of `ruthless attach` through a Unix socket, from which it takes their input.
+pub(crate) struct Monitor {
+    socket: PathBuf,
+    listener: UnixListener,
+    clients: Vec<UnixStream>,
+    outputs: Vec<(RawFd, u8)>,
+    input: Option<RawFd>,
+    pending_input: Vec<u8>,
+    close_input: bool,
+    pty: Option<Pty>,
+    logs: JailLogs,
+    logger: Logger,
+}
+
+impl Monitor {
+    pub(crate) fn new(
+        container: &str,
+        pipes: Option<Pipes>,
+        pty: Option<Pty>,
+        interactive: bool,
+        log: &LogConfig,
+    ) -> Result<Monitor, Error> {
+        DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(runtime_folder())?;
+        let socket = socket_path(container);
+        // A leftover of a monitor that didn't finish cleanly, as the cgroup of the container
+        // couldn't have been created otherwise.
+        if socket.exists() {
+            remove_file(&socket)?;
+        }
+        let listener = UnixListener::bind(&socket)?;
+        let (outputs, input) = match (pipes, pty) {
+            (Some(pipes), _) => (
+                vec![
+                    (pipes.stdout.0, FRAME_STDOUT),
+                    (pipes.stderr.0, FRAME_STDERR),
+                ],
+                pipes.stdin.map(|(_, write)| write),
+            ),
+            (None, Some(pty)) => (
+                vec![(pty.master(), FRAME_STDOUT)],
+                Some(pty.master()).filter(|_| interactive),
+            ),
+            (None, None) => (vec![], None),
+        };
+        // Writes to the payload never block, as it would stall its output and the clients.
+        if let Some(fd) = input {
+            fcntl(fd, FcntlArg::F_SETFL(OFlag::O_NONBLOCK))?;
+        }
+        let logs = JailLogs::create(container)?;
+        Ok(Monitor {
+            socket,
+            listener,
+            clients: vec![],
+            outputs,
+            input,
+            pending_input: vec![],
+            close_input: false,
+            pty,
+            logger: logs.logger(log)?,
+            logs,
+        })
+    }
+
+    pub(crate) fn run(&mut self, pid: Pid) -> Result<isize, Error> {
+        let mut child_signals = SigSet::empty();
+        child_signals.add(Signal::SIGCHLD);
+        child_signals.thread_block()?;
+        let mut child_exit = SignalFd::new(&child_signals)?;
+        // The container could have finished before SIGCHLD was blocked.
+        let mut exit_code = try_wait(pid)?;
+        while exit_code.is_none() {
+            let mut fds = vec![
+                PollFd::new(self.listener.as_raw_fd(), EventFlags::POLLIN),
+                PollFd::new(child_exit.as_raw_fd(), EventFlags::POLLIN),
+            ];
+            fds.extend(
+                self.outputs
+                    .iter()
+                    .map(|(fd, _)| PollFd::new(*fd, EventFlags::POLLIN)),
+            );
+            fds.extend(
+                self.clients
+                    .iter()
+                    .map(|c| PollFd::new(c.as_raw_fd(), EventFlags::POLLIN)),
+            );
+            let outputs_end = 2 + self.outputs.len();
+            let clients_end = outputs_end + self.clients.len();
+            if let Some(fd) = self.input.filter(|_| !self.pending_input.is_empty()) {
+                fds.push(PollFd::new(fd, EventFlags::POLLOUT));
+            }
+            if !poll_ready(&mut fds, NO_TIMEOUT)? {
+                continue;
+            }
+            let ready_outputs = fds[2..outputs_end].iter().map(is_ready).collect::<Vec<_>>();
+            let ready_clients = fds[outputs_end..clients_end]
+                .iter()
+                .map(is_ready)
+                .collect::<Vec<_>>();
+            if fds[clients_end..].iter().any(is_ready) {
+                self.flush_input()?;
+            }
+            if is_ready(&fds[0]) {
+                self.accept()?;
+            }
+            if is_ready(&fds[1]) {
+                child_exit.read_signal()?;
+                exit_code = try_wait(pid)?;
+            }
+            self.relay_outputs(&ready_outputs)?;
+            self.relay_clients(&ready_clients)?;
+        }
+        self.drain()?;
+        self.logger.flush()?;
+        let exit_code = exit_code.unwrap_or_default();
+        for client in self.clients.iter_mut() {
+            write_frame(client, FRAME_EXIT, &(exit_code as i32).to_be_bytes()).ok();
+        }
+        self.logs.record_exit_code(exit_code)?;
+        Ok(exit_code)
+    }
+
+    fn accept(&mut self) -> Result<(), Error> {
+        let (mut client, _) = self.listener.accept()?;
+        client.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+        client.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+        if self.pty.is_none() || write_frame(&mut client, FRAME_TTY, &[]).is_ok() {
+            self.clients.push(client);
+        }
+        Ok(())
+    }
+
+    // Indexes in [ready] match the ones of the outputs, so they are walked backwards to remove the
+    // closed ones.
+    fn relay_outputs(&mut self, ready: &[bool]) -> Result<(), Error> {
+        let mut buffer = [0; BUFFER_SIZE];
+        for i in (0..ready.len()).rev().filter(|i| ready[*i]) {
+            let (fd, kind) = self.outputs[i];
+            match read(fd, &mut buffer) {
+                Ok(0) | Err(SyscallError::Sys(Errno::EIO)) => {
+                    self.outputs.remove(i);
+                }
+                Ok(n) => self.broadcast(kind, &buffer[..n])?,
+                Err(SyscallError::Sys(Errno::EINTR)) | Err(SyscallError::Sys(Errno::EAGAIN)) => {}
+                Err(e) => Err(e)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn broadcast(&mut self, kind: u8, data: &[u8]) -> Result<(), Error> {
+        let stream = match kind {
+            FRAME_STDERR => Stream::Stderr,
+            _ => Stream::Stdout,
+        };
+        self.logger.write(stream, data)?;
+        // Clients that can't keep up are disconnected instead of stalling the container.
+        self.clients
+            .retain(|c| write_frame(&mut &*c, kind, data).is_ok());
+        Ok(())
+    }
+
+    fn relay_clients(&mut self, ready: &[bool]) -> Result<(), Error> {
+        for i in (0..ready.len()).rev().filter(|i| ready[*i]) {
+            match read_frame(&mut self.clients[i]) {
+                Ok(Some((FRAME_STDIN, data))) => {
+                    if self.input.is_some()
+                        && !self.close_input
+                        && self.pending_input.len() + data.len() <= MAX_PENDING_INPUT
+                    {
+                        self.pending_input.extend_from_slice(&data);
+                        self.flush_input()?;
+                    }
+                }
+                Ok(Some((FRAME_CLOSE_STDIN, _))) if self.input.is_some() => {
+                    if self.pty.is_some() {
+                        if self.pending_input.len() < MAX_PENDING_INPUT {
+                            self.pending_input.push(END_OF_TRANSMISSION);
+                        }
+                    } else {
+                        self.close_input = true;
+                    }
+                    self.flush_input()?;
+                }
+                Ok(Some((FRAME_RESIZE, size))) if size.len() == 4 => {
+                    if let Some(pty) = self.pty {
+                        pty.resize(&Winsize {
+                            ws_row: u16::from_be_bytes([size[0], size[1]]),
+                            ws_col: u16::from_be_bytes([size[2], size[3]]),
+                            ws_xpixel: 0,
+                            ws_ypixel: 0,
+                        })?;
+                    }
+                }
+                Ok(Some(_)) => {}
+                Ok(None) | Err(_) => {
+                    self.clients.remove(i);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Writes as much of the pending input as the payload takes without blocking. The standard input
+    // of the payload is closed once a client asked for it and the input before was written.
+    fn flush_input(&mut self) -> Result<(), Error> {
+        let fd = match self.input {
+            Some(fd) => fd,
+            None => return Ok(()),
+        };
+        while !self.pending_input.is_empty() {
+            match write(fd, &self.pending_input) {
+                Ok(n) => {
+                    self.pending_input.drain(..n);
+                }
+                Err(SyscallError::Sys(Errno::EINTR)) => {}
+                Err(SyscallError::Sys(Errno::EAGAIN)) => return Ok(()),
+                // The payload closed its standard input.
+                Err(_) => {
+                    self.pending_input.clear();
+                    self.input = None;
+                    return Ok(());
+                }
+            }
+        }
+        if self.close_input {
+            close(fd)?;
+            self.input = None;
+        }
+        Ok(())
+    }
+
+    // Relays what's left of the output once the container finished.
+    fn drain(&mut self) -> Result<(), Error> {
+        while !self.outputs.is_empty() {
+            let mut fds = self
+                .outputs
+                .iter()
+                .map(|(fd, _)| PollFd::new(*fd, EventFlags::POLLIN))
+                .collect::<Vec<_>>();
+            match poll(&mut fds, DRAIN_TIMEOUT) {
+                Ok(0) => break,
+                Err(SyscallError::Sys(Errno::EINTR)) => continue,
+                result => result?,
+            };
+            let ready = fds.iter().map(is_ready).collect::<Vec<_>>();
+            self.relay_outputs(&ready)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Monitor {
+    fn drop(&mut self) {
+        remove_file(&self.socket).ok();
+    }
+}
+
+fn send_window_size(stream: &mut UnixStream) -> Result<(), Error> {
+    if let Some(size) = window_size(STDIN_FILENO) {
+        let mut payload = size.ws_row.to_be_bytes().to_vec();
+        payload.extend_from_slice(&size.ws_col.to_be_bytes());
+        write_frame(stream, FRAME_RESIZE, &payload)?;
+    }
+    Ok(())
+}
+
+// Relays the streams of a detached container to the ones of ruthless until it finishes, returning
+// its exit code, or until [detach_keys] are typed. Keys that only start the sequence are held back
+// until it's clear they don't complete it.
+pub(crate) fn attach(container: &str, detach_keys: &[u8]) -> Result<isize, Error> {
+    let mut stream = UnixStream::connect(socket_path(container))
+        .map_err(|_| MonitorError::ContainerNotAttachable(container.to_owned()))?;
+    let mut resize_signals = SigSet::empty();
+    resize_signals.add(Signal::SIGWINCH);
+    resize_signals.thread_block()?;
+    let mut resize = SignalFd::new(&resize_signals)?;
+    let mut _raw_terminal = None;
+    let mut tty = false;
+    let mut stdin_open = true;
+    let mut matched = 0;
+    let mut buffer = [0; BUFFER_SIZE];
+    loop {
+        let mut fds = vec![
+            PollFd::new(stream.as_raw_fd(), EventFlags::POLLIN),
+            PollFd::new(resize.as_raw_fd(), EventFlags::POLLIN),
+        ];
+        if stdin_open {
+            fds.push(PollFd::new(STDIN_FILENO, EventFlags::POLLIN));
+        }
+        if !poll_ready(&mut fds, NO_TIMEOUT)? {
+            continue;
+        }
+        if is_ready(&fds[0]) {
+            match read_frame(&mut stream)? {
+                Some((FRAME_STDOUT, data)) => write_all(STDOUT_FILENO, &data)?,
+                Some((FRAME_STDERR, data)) => write_all(STDERR_FILENO, &data)?,
+                Some((FRAME_TTY, _)) => {
+                    tty = true;
+                    _raw_terminal = RawTerminal::new(STDIN_FILENO)?;
+                    send_window_size(&mut stream)?;
+                }
+                Some((FRAME_EXIT, code)) if code.len() == 4 => {
+                    return Ok(i32::from_be_bytes([code[0], code[1], code[2], code[3]]) as isize);
+                }
+                Some(_) => {}
+                None => Err(MonitorError::ConnectionLost(container.to_owned()))?,
+            }
+        }
+        if is_ready(&fds[1]) {
+            resize.read_signal()?;
+            if tty {
+                send_window_size(&mut stream)?;
+            }
+        }
+        if stdin_open && is_ready(&fds[2]) {
+            let n = read(STDIN_FILENO, &mut buffer)?;
+            if n == 0 {
+                stdin_open = false;
+                write_frame(&mut stream, FRAME_CLOSE_STDIN, &[])?;
+                continue;
+            }
+            let mut input = vec![];
+            for byte in buffer[..n].iter() {
+                if *byte == detach_keys[matched] {
+                    matched += 1;
+                    if matched == detach_keys.len() {
+                        return Ok(0);
+                    }
+                    continue;
+                }
+                input.extend_from_slice(&detach_keys[..matched]);
+                matched = 0;
+                if *byte == detach_keys[0] {
+                    matched = 1;
+                } else {
+                    input.push(*byte);
+                }
+            }
+            if !input.is_empty() {
+                write_frame(&mut stream, FRAME_STDIN, &input)?;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_detach_keys() {
+        assert_eq!(
+            parse_detach_keys(DEFAULT_DETACH_KEYS).unwrap(),
+            vec![16, 17]
+        );
+        assert_eq!(parse_detach_keys("ctrl-a,ctrl-z").unwrap(), vec![1, 26]);
+        assert_eq!(parse_detach_keys("ctrl-A,ctrl-@").unwrap(), vec![1, 0]);
+        assert_eq!(parse_detach_keys("ctrl-[,ctrl-_").unwrap(), vec![27, 31]);
+        assert_eq!(
+            parse_detach_keys("q,ctrl-c,Q").unwrap(),
+            vec![b'q', 3, b'Q']
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_detach_keys() {
+        for keys in [
+            "", "ctrl-", "ctrl-1", "ctrl-ab", "ab", "a,,b", "a,", "é", "ctrl-é",
+        ]
+        .iter()
+        {
+            assert!(parse_detach_keys(keys).is_none(), "{}", keys);
+        }
+    }
+}
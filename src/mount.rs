@@ -1,15 +1,480 @@
 use failure::Error;
-use nix::mount::{mount, umount, MsFlags};
+use nix::mount::{mount, umount2, MntFlags, MsFlags};
+use nix::sys::statvfs::{statvfs, FsFlags};
+use nix::unistd::{chdir, pivot_root};
+use std::ffi::OsString;
+use std::fs::{create_dir_all, metadata, read_link, read_to_string, symlink_metadata, OpenOptions};
+use std::os::unix::fs::symlink;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
 
 pub(crate) const MOUNTS_FILE: &str = "/proc/mounts";
+const ROOT: &str = "/";
+const CURRENT_DIRECTORY: &str = ".";
+const NONE: Option<&'static [u8]> = None;
+const READ_ONLY: &str = "ro";
+const READ_WRITE: &str = "rw";
+const BIND_TYPE: &str = "bind";
+const TMPFS: &str = "tmpfs";
+const DEVPTS: &str = "devpts";
+const MQUEUE: &str = "mqueue";
+const SHM: &str = "shm";
+const SYSFS: &str = "sysfs";
+const DEV: &str = "/dev";
+const DEV_PTS: &str = "/dev/pts";
+const DEV_SHM: &str = "/dev/shm";
+const DEV_MQUEUE: &str = "/dev/mqueue";
+const SYS: &str = "/sys";
+const DEV_DATA: &str = "mode=755,size=65536k";
+const DEVPTS_DATA: &str = "newinstance,ptmxmode=0666,mode=0620";
+const MODE_OPTION: &str = "mode=";
+const SHM_MODE: &str = "1777";
+const DEFAULT_TMPFS_MODE: &str = "mode=1777";
+pub(crate) const DEFAULT_SHM_SIZE: u64 = 64 * 1024 * 1024;
+const DEV_NULL: &str = "/dev/null";
+const PARENT_DIRECTORY: &str = "..";
+const MAX_SYMLINKS: usize = 40;
+const MASKED_PATHS: &[&str] = &[
+    "/proc/acpi",
+    "/proc/asound",
+    "/proc/interrupts",
+    "/proc/kcore",
+    "/proc/keys",
+    "/proc/latency_stats",
+    "/proc/sched_debug",
+    "/proc/scsi",
+    "/proc/timer_list",
+    "/proc/timer_stats",
+    "/sys/devices/virtual/powercap",
+    "/sys/firmware",
+];
+const READ_ONLY_PATHS: &[&str] = &[
+    "/proc/bus",
+    "/proc/fs",
+    "/proc/irq",
+    "/proc/sys",
+    "/proc/sysrq-trigger",
+];
+const DEVICES: &[&str] = &["null", "zero", "full", "random", "urandom", "tty"];
+const DEV_SYMLINKS: &[(&str, &str)] = &[
+    ("fd", "/proc/self/fd"),
+    ("stdin", "/proc/self/fd/0"),
+    ("stdout", "/proc/self/fd/1"),
+    ("stderr", "/proc/self/fd/2"),
+    ("ptmx", "pts/ptmx"),
+];
+// Name, flag and whether the option sets or clears the flag.
+const MOUNT_FLAGS: &[(&str, MsFlags, bool)] = &[
+    ("ro", MsFlags::MS_RDONLY, true),
+    ("rw", MsFlags::MS_RDONLY, false),
+    ("nosuid", MsFlags::MS_NOSUID, true),
+    ("suid", MsFlags::MS_NOSUID, false),
+    ("nodev", MsFlags::MS_NODEV, true),
+    ("dev", MsFlags::MS_NODEV, false),
+    ("noexec", MsFlags::MS_NOEXEC, true),
+    ("exec", MsFlags::MS_NOEXEC, false),
+    ("sync", MsFlags::MS_SYNCHRONOUS, true),
+    ("async", MsFlags::MS_SYNCHRONOUS, false),
+    ("noatime", MsFlags::MS_NOATIME, true),
+    ("atime", MsFlags::MS_NOATIME, false),
+    ("nodiratime", MsFlags::MS_NODIRATIME, true),
+    ("diratime", MsFlags::MS_NODIRATIME, false),
+    ("relatime", MsFlags::MS_RELATIME, true),
+    ("norelatime", MsFlags::MS_RELATIME, false),
+    ("strictatime", MsFlags::MS_STRICTATIME, true),
+];
+// Flags that the kernel locks on mounts inherited from another user namespace. A remount has to
+// keep them or it fails with EPERM.
+const LOCKED_FLAGS: &[(FsFlags, MsFlags)] = &[
+    (FsFlags::ST_NOSUID, MsFlags::MS_NOSUID),
+    (FsFlags::ST_NODEV, MsFlags::MS_NODEV),
+    (FsFlags::ST_NOEXEC, MsFlags::MS_NOEXEC),
+    (FsFlags::ST_NOATIME, MsFlags::MS_NOATIME),
+    (FsFlags::ST_NODIRATIME, MsFlags::MS_NODIRATIME),
+    (FsFlags::ST_RELATIME, MsFlags::MS_RELATIME),
+];
+
+#[derive(Debug, Fail)]
+pub(crate) enum MountError {
+    #[fail(display = "Invalid volume {}", 0)]
+    InvalidVolume(String),
+    #[fail(display = "Unsupported mount type {}", 0)]
+    UnsupportedMountType(String),
+    #[fail(display = "Unknown mount option {}", 0)]
+    UnknownMountOption(String),
+    #[fail(display = "Mount paths should be absolute, got {}", 0)]
+    RelativePath(String),
+    #[fail(display = "Volume source {} doesn't exist", 0)]
+    MissingSource(String),
+    #[fail(display = "Mount paths can't contain .., got {}", 0)]
+    ParentDirectory(String),
+    #[fail(display = "Too many levels of symbolic links resolving {}", 0)]
+    TooManySymlinks(String),
+}
+
+#[derive(Clone)]
+pub(crate) struct Volume {
+    source: String,
+    destination: String,
+    read_only: bool,
+}
+
+#[inline]
+fn check_absolute(path: &str) -> Result<String, MountError> {
+    if !path.starts_with('/') {
+        Err(MountError::RelativePath(path.to_owned()))
+    } else if path.split('/').any(|c| c == PARENT_DIRECTORY) {
+        Err(MountError::ParentDirectory(path.to_owned()))
+    } else {
+        Ok(path.to_owned())
+    }
+}
+
+#[inline]
+fn parse_read_only(value: &str) -> Option<bool> {
+    match value {
+        "" | "1" | "true" => Some(true),
+        "0" | "false" => Some(false),
+        _ => None,
+    }
+}
+
+impl FromStr for Volume {
+    type Err = MountError;
+
+    // Parses the short form of --volume: [source]:[destination][:ro|rw]
+    fn from_str(s: &str) -> Result<Volume, Self::Err> {
+        let (source, destination, read_only) = match s.split(':').collect::<Vec<&str>>().as_slice()
+        {
+            [source, destination] => (*source, *destination, false),
+            [source, destination, READ_ONLY] => (*source, *destination, true),
+            [source, destination, READ_WRITE] => (*source, *destination, false),
+            _ => Err(MountError::InvalidVolume(s.to_owned()))?,
+        };
+        Ok(Volume {
+            source: check_absolute(source)?,
+            destination: check_absolute(destination)?,
+            read_only,
+        })
+    }
+}
+
+impl Volume {
+    // Parses the long form of --mount: type=bind,src=[source],dst=[destination][,ro]
+    pub(crate) fn from_mount_option(option: &str) -> Result<Volume, MountError> {
+        let mut source = None;
+        let mut destination = None;
+        let mut read_only = false;
+        for field in option.split(',') {
+            let mut parts = field.splitn(2, '=');
+            let key = parts.next().unwrap_or("");
+            let value = parts.next().unwrap_or("");
+            match key {
+                "type" if value == BIND_TYPE => {}
+                "type" => Err(MountError::UnsupportedMountType(value.to_owned()))?,
+                "src" | "source" => source = Some(check_absolute(value)?),
+                "dst" | "destination" | "target" => destination = Some(check_absolute(value)?),
+                "ro" | "readonly" => {
+                    read_only = parse_read_only(value)
+                        .ok_or_else(|| MountError::UnknownMountOption(field.to_owned()))?
+                }
+                _ => Err(MountError::UnknownMountOption(field.to_owned()))?,
+            }
+        }
+        match (source, destination) {
+            (Some(source), Some(destination)) => Ok(Volume {
+                source,
+                destination,
+                read_only,
+            }),
+            _ => Err(MountError::InvalidVolume(option.to_owned())),
+        }
+    }
+
+    pub(crate) fn mount(&self, root: &str) -> Result<Mount, Error> {
+        Mount::bind(
+            &self.source,
+            resolve_in_root(root, &self.destination)?,
+            self.read_only,
+        )
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct Tmpfs {
+    destination: String,
+    flags: MsFlags,
+    data: String,
+}
+
+impl FromStr for Tmpfs {
+    type Err = MountError;
+
+    // Parses --tmpfs: [destination][:option,option...]
+    fn from_str(s: &str) -> Result<Tmpfs, Self::Err> {
+        let mut parts = s.splitn(2, ':');
+        let destination = check_absolute(parts.next().unwrap_or(""))?;
+        let mut flags = MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC;
+        let mut data = vec![];
+        for option in parts.next().unwrap_or("").split(',') {
+            match MOUNT_FLAGS.iter().find(|(name, _, _)| *name == option) {
+                Some((_, flag, true)) => flags.insert(*flag),
+                Some((_, flag, false)) => flags.remove(*flag),
+                None if option.is_empty() => {}
+                None => data.push(option),
+            }
+        }
+        if !data.iter().any(|o| o.starts_with(MODE_OPTION)) {
+            data.push(DEFAULT_TMPFS_MODE);
+        }
+        Ok(Tmpfs {
+            destination,
+            flags,
+            data: data.join(","),
+        })
+    }
+}
+
+impl Tmpfs {
+    pub(crate) fn mount(&self, root: &str) -> Result<Mount, Error> {
+        Mount::filesystem(
+            TMPFS,
+            resolve_in_root(root, &self.destination)?,
+            TMPFS,
+            self.flags,
+            &self.data,
+        )
+    }
+}
+
+// The components of [path] in reverse order, to be resolved by popping them. `..` is kept as is.
+fn reversed_components(path: &Path) -> Vec<OsString> {
+    path.components()
+        .rev()
+        .filter_map(|c| match c {
+            Component::Normal(name) => Some(name.to_owned()),
+            Component::ParentDir => Some(OsString::from(PARENT_DIRECTORY)),
+            _ => None,
+        })
+        .collect()
+}
+
+// Resolves [path] under [root] like openat2 with RESOLVE_IN_ROOT does: the symlinks of the image
+// are followed as if [root] was /, and `..` never goes above it, so that mounts can't be pointed
+// at paths of the host. The missing part of the path is left for the mount to create.
+fn resolve_in_root(root: &str, path: &str) -> Result<String, Error> {
+    let root = Path::new(root);
+    let mut resolved = PathBuf::new();
+    let mut pending = reversed_components(Path::new(path));
+    let mut symlinks = 0;
+    while let Some(component) = pending.pop() {
+        if component == PARENT_DIRECTORY {
+            resolved.pop();
+            continue;
+        }
+        let candidate = resolved.join(&component);
+        match symlink_metadata(root.join(&candidate)) {
+            Ok(m) if m.file_type().is_symlink() => {
+                symlinks += 1;
+                if symlinks > MAX_SYMLINKS {
+                    Err(MountError::TooManySymlinks(path.to_owned()))?
+                }
+                let target = read_link(root.join(&candidate))?;
+                if target.is_absolute() {
+                    resolved = PathBuf::new();
+                }
+                pending.append(&mut reversed_components(&target));
+            }
+            _ => resolved = candidate,
+        }
+    }
+    Ok(format!(
+        "{}/{}",
+        root.to_string_lossy().trim_end_matches('/'),
+        resolved.to_string_lossy()
+    ))
+}
+
+// Populates a minimal /dev with the devices of the host bound on a tmpfs, a private devpts
+// instance, /dev/shm and /dev/mqueue. Only /dev itself comes from the image, everything below is
+// created on the fresh tmpfs.
+pub(crate) fn mount_devices(root: &str, shm_size: u64) -> Result<Vec<Mount>, Error> {
+    let dev = resolve_in_root(root, DEV)?;
+    let mut mounts = vec![Mount::filesystem(
+        TMPFS,
+        dev.clone(),
+        TMPFS,
+        MsFlags::MS_NOSUID | MsFlags::MS_STRICTATIME,
+        DEV_DATA,
+    )?];
+    for device in DEVICES.iter() {
+        let source = format!("{}/{}", DEV, device);
+        mounts.push(Mount::bind(
+            &source,
+            resolve_in_root(root, &source)?,
+            false,
+        )?);
+    }
+    for (link, target) in DEV_SYMLINKS.iter() {
+        symlink(target, format!("{}/{}", dev, link))?;
+    }
+    mounts.push(Mount::filesystem(
+        DEVPTS,
+        resolve_in_root(root, DEV_PTS)?,
+        DEVPTS,
+        MsFlags::MS_NOSUID | MsFlags::MS_NOEXEC,
+        DEVPTS_DATA,
+    )?);
+    mounts.push(Mount::filesystem(
+        SHM,
+        resolve_in_root(root, DEV_SHM)?,
+        TMPFS,
+        MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC,
+        &format!("{}{},size={}", MODE_OPTION, SHM_MODE, shm_size),
+    )?);
+    mounts.push(Mount::filesystem(
+        MQUEUE,
+        resolve_in_root(root, DEV_MQUEUE)?,
+        MQUEUE,
+        MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC,
+        "",
+    )?);
+    Ok(mounts)
+}
+
+// A fresh sysfs can only be mounted by the owner of the network namespace, so containers sharing
+// the network of the host get a read-only view of the sysfs of the host instead, including the
+// mounts below it like /sys/fs/cgroup.
+pub(crate) fn mount_sysfs(root: &str) -> Result<Mount, Error> {
+    let target = resolve_in_root(root, SYS)?;
+    Mount::filesystem(
+        SYSFS,
+        target.clone(),
+        SYSFS,
+        MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC | MsFlags::MS_RDONLY,
+        "",
+    )
+    .or_else(|_| Mount::bind(SYS, target, true))
+}
+
+// Mount points in /proc/mounts have spaces, tabs, newlines and backslashes escaped as \ooo.
+fn unescape_mount_point(mount_point: &str) -> String {
+    let bytes = mount_point.as_bytes();
+    let mut unescaped = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        match mount_point
+            .get(i + 1..i + 4)
+            .filter(|_| bytes[i] == b'\\')
+            .and_then(|octal| u8::from_str_radix(octal, 8).ok())
+        {
+            Some(byte) => {
+                unescaped.push(byte);
+                i += 4;
+            }
+            None => {
+                unescaped.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&unescaped).into_owned()
+}
+
+// A read-only remount only applies to a single mount, so the ones below [target] are remounted too.
+fn remount_read_only_recursively(target: &str) -> Result<(), Error> {
+    let prefix = format!("{}/", target.trim_end_matches('/'));
+    let mount_points = read_to_string(MOUNTS_FILE)?
+        .lines()
+        .filter_map(|l| l.split(' ').nth(1).map(unescape_mount_point))
+        .filter(|m| m == target || m.starts_with(&prefix))
+        .collect::<Vec<String>>();
+    for mount_point in mount_points {
+        remount_read_only(&mount_point)?;
+    }
+    Ok(())
+}
+
+fn remount_read_only(target: &str) -> Result<(), Error> {
+    let current = statvfs(target)?.flags();
+    let flags = LOCKED_FLAGS
+        .iter()
+        .filter(|(f, _)| current.contains(*f))
+        .fold(
+            MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY,
+            |flags, (_, m)| flags | *m,
+        );
+    mount(NONE, target, NONE, flags, NONE)?;
+    Ok(())
+}
+
+// Hides the paths of /proc and /sys that leak information of the host, mounting an empty read-only
+// tmpfs over directories and /dev/null over files, and makes the ones that can reconfigure the
+// host read-only. Paths missing in this kernel are skipped.
+pub(crate) fn mask_paths() -> Result<Vec<Mount>, Error> {
+    let mut mounts = vec![];
+    for path in MASKED_PATHS.iter() {
+        match metadata(path) {
+            Ok(m) if m.is_dir() => mounts.push(Mount::filesystem(
+                TMPFS,
+                path.to_string(),
+                TMPFS,
+                MsFlags::MS_RDONLY,
+                "",
+            )?),
+            Ok(_) => mounts.push(Mount::bind(DEV_NULL, path.to_string(), false)?),
+            Err(_) => {}
+        }
+    }
+    for path in READ_ONLY_PATHS.iter() {
+        if metadata(path).is_ok() {
+            mounts.push(Mount::bind(path, path.to_string(), true)?);
+        }
+    }
+    Ok(mounts)
+}
+
+pub(crate) fn make_root_read_only() -> Result<(), Error> {
+    remount_read_only(ROOT)
+}
+
+pub(crate) fn make_mounts_private() -> Result<(), Error> {
+    mount(
+        NONE,
+        ROOT,
+        NONE,
+        MsFlags::MS_REC | MsFlags::MS_PRIVATE,
+        NONE,
+    )?;
+    Ok(())
+}
+
+// The mounts are made under the new root before pivoting, while their sources are still
+// reachable. They are returned relative to the new root, in reverse order so that nested mounts
+// are released before their parents.
+pub(crate) fn switch_root(new_root: &str, mounts: Vec<Mount>) -> Result<Vec<Mount>, Error> {
+    mount(
+        Some(new_root),
+        new_root,
+        NONE,
+        MsFlags::MS_BIND | MsFlags::MS_REC,
+        NONE,
+    )?;
+    chdir(new_root)?;
+    pivot_root(CURRENT_DIRECTORY, CURRENT_DIRECTORY)?;
+    umount2(CURRENT_DIRECTORY, MntFlags::MNT_DETACH)?;
+    chdir(ROOT)?;
+    let root = new_root.trim_end_matches('/');
+    Ok(mounts.into_iter().rev().map(|m| m.rebase(root)).collect())
+}
 
 pub(crate) struct Mount {
     target: String,
+    flags: MntFlags,
 }
 
 impl Mount {
     pub(crate) fn new(resource: String, target: String, fs_type: String) -> Result<Mount, Error> {
-        const NONE: Option<&'static [u8]> = None;
         mount(
             Some(resource.as_str()),
             target.as_str(),
@@ -17,12 +482,220 @@ impl Mount {
             MsFlags::empty(),
             NONE,
         )?;
-        Ok(Mount { target })
+        Ok(Mount {
+            target,
+            flags: MntFlags::empty(),
+        })
+    }
+
+    fn filesystem(
+        resource: &str,
+        target: String,
+        fs_type: &str,
+        flags: MsFlags,
+        data: &str,
+    ) -> Result<Mount, Error> {
+        create_dir_all(&target)?;
+        mount(
+            Some(resource),
+            target.as_str(),
+            Some(fs_type),
+            flags,
+            if data.is_empty() { None } else { Some(data) },
+        )?;
+        Ok(Mount {
+            target,
+            flags: MntFlags::MNT_DETACH,
+        })
+    }
+
+    fn bind(source: &str, target: String, read_only: bool) -> Result<Mount, Error> {
+        let source_metadata =
+            metadata(source).map_err(|_| MountError::MissingSource(source.to_owned()))?;
+        if source_metadata.is_dir() {
+            create_dir_all(&target)?;
+        } else if metadata(&target).is_err() {
+            if let Some(parent) = Path::new(&target).parent() {
+                create_dir_all(parent)?;
+            }
+            OpenOptions::new().create(true).append(true).open(&target)?;
+        }
+        mount(
+            Some(source),
+            target.as_str(),
+            NONE,
+            MsFlags::MS_BIND | MsFlags::MS_REC,
+            NONE,
+        )?;
+        let bind = Mount {
+            target,
+            flags: MntFlags::MNT_DETACH,
+        };
+        if read_only {
+            remount_read_only_recursively(&bind.target)?;
+        }
+        Ok(bind)
+    }
+
+    fn rebase(mut self, root: &str) -> Mount {
+        self.target = self.target.split_off(root.len());
+        self
     }
 }
 
 impl Drop for Mount {
     fn drop(&mut self) {
-        umount(self.target.as_str()).unwrap();
+        umount2(self.target.as_str(), self.flags).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn volume(volume: Result<Volume, MountError>) -> (String, String, bool) {
+        let volume = volume.unwrap();
+        (volume.source, volume.destination, volume.read_only)
+    }
+
+    fn bind(source: &str, destination: &str, read_only: bool) -> (String, String, bool) {
+        (source.to_owned(), destination.to_owned(), read_only)
+    }
+
+    #[test]
+    fn parses_volumes() {
+        assert_eq!(
+            volume(Volume::from_str("/srv/data:/data")),
+            bind("/srv/data", "/data", false)
+        );
+        assert_eq!(
+            volume(Volume::from_str("/srv/data:/data:ro")),
+            bind("/srv/data", "/data", true)
+        );
+        assert_eq!(
+            volume(Volume::from_str("/srv/data:/data:rw")),
+            bind("/srv/data", "/data", false)
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_volumes() {
+        let invalid = |v| matches!(Volume::from_str(v), Err(MountError::InvalidVolume(_)));
+        assert!(invalid("/srv/data"));
+        assert!(invalid("/srv/data:/data:exec"));
+        assert!(invalid("/srv/data:/data:ro:rw"));
+        assert!(matches!(
+            Volume::from_str("data:/data"),
+            Err(MountError::RelativePath(_))
+        ));
+        assert!(matches!(
+            Volume::from_str("/srv/data:data"),
+            Err(MountError::RelativePath(_))
+        ));
+        assert!(matches!(
+            Volume::from_str("/srv/data:/data/../etc"),
+            Err(MountError::ParentDirectory(_))
+        ));
+    }
+
+    #[test]
+    fn parses_mount_options() {
+        assert_eq!(
+            volume(Volume::from_mount_option(
+                "type=bind,src=/srv/data,dst=/data"
+            )),
+            bind("/srv/data", "/data", false)
+        );
+        assert_eq!(
+            volume(Volume::from_mount_option(
+                "source=/srv/data,target=/data,readonly"
+            )),
+            bind("/srv/data", "/data", true)
+        );
+        assert_eq!(
+            volume(Volume::from_mount_option(
+                "destination=/data,ro=false,src=/srv/data"
+            )),
+            bind("/srv/data", "/data", false)
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_mount_options() {
+        let parse = Volume::from_mount_option;
+        assert!(matches!(
+            parse("type=volume,src=/srv/data,dst=/data"),
+            Err(MountError::UnsupportedMountType(_))
+        ));
+        assert!(matches!(
+            parse("src=/srv/data,dst=/data,ro=maybe"),
+            Err(MountError::UnknownMountOption(_))
+        ));
+        assert!(matches!(
+            parse("src=/srv/data,dst=/data,exec"),
+            Err(MountError::UnknownMountOption(_))
+        ));
+        assert!(matches!(
+            parse("src=/srv/data"),
+            Err(MountError::InvalidVolume(_))
+        ));
+        assert!(matches!(
+            parse("src=srv/data,dst=/data"),
+            Err(MountError::RelativePath(_))
+        ));
+        assert!(matches!(
+            parse("src=/srv/data,dst=/data/.."),
+            Err(MountError::ParentDirectory(_))
+        ));
+    }
+
+    fn tmpfs(tmpfs: &str) -> (String, MsFlags, String) {
+        let tmpfs = Tmpfs::from_str(tmpfs).unwrap();
+        (tmpfs.destination, tmpfs.flags, tmpfs.data)
+    }
+
+    #[test]
+    fn parses_tmpfs() {
+        let default_flags = MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC;
+        assert_eq!(
+            tmpfs("/run"),
+            (
+                "/run".to_owned(),
+                default_flags,
+                DEFAULT_TMPFS_MODE.to_owned()
+            )
+        );
+        assert_eq!(
+            tmpfs("/run:size=64m,exec,ro"),
+            (
+                "/run".to_owned(),
+                MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_RDONLY,
+                "size=64m,mode=1777".to_owned()
+            )
+        );
+        assert_eq!(
+            tmpfs("/run:mode=700,,dev"),
+            (
+                "/run".to_owned(),
+                MsFlags::MS_NOSUID | MsFlags::MS_NOEXEC,
+                "mode=700".to_owned()
+            )
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_tmpfs() {
+        assert!(matches!(
+            Tmpfs::from_str(""),
+            Err(MountError::RelativePath(_))
+        ));
+        assert!(matches!(
+            Tmpfs::from_str("run:size=64m"),
+            Err(MountError::RelativePath(_))
+        ));
+        assert!(matches!(
+            Tmpfs::from_str("/run/../etc"),
+            Err(MountError::ParentDirectory(_))
+        ));
     }
 }
@@ -0,0 +1,483 @@
+use failure::Error;
+use nix::errno::Errno;
+use nix::fcntl::{open, OFlag};
+use nix::libc::{c_short, fcntl, F_SETFD, IFF_UP, SIOCGIFFLAGS, SIOCSIFFLAGS};
+use nix::sched::{setns, CloneFlags};
+use nix::sys::signal::{kill, SIGTERM};
+use nix::sys::socket::{socket, AddressFamily, SockFlag, SockType};
+use nix::sys::stat::Mode;
+use nix::sys::wait::waitpid;
+use nix::unistd::{close, fork, pipe2, read, ForkResult, Pid};
+use nix::Error as SyscallError;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::io::{self, copy, ErrorKind};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::os::unix::io::RawFd;
+use std::os::unix::process::CommandExt;
+use std::process::{exit, Child, Command};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{spawn, JoinHandle};
+use std::time::Duration;
+
+const IFNAMSIZ: usize = 16;
+const IFREQ_PADDING: usize = 22;
+const LOOPBACK_INTERFACE: &str = "lo";
+const SLIRP_COMMAND: &str = "slirp4netns";
+const SLIRP_TAP_INTERFACE: &str = "tap0";
+const SLIRP_MTU: &str = "--mtu=65520";
+const ANY_ADDRESS: &str = "0.0.0.0";
+const LOCALHOST: &str = "127.0.0.1";
+const UDP_BUFFER_SIZE: usize = 65536;
+// How long the socket and thread of a UDP client are kept without datagrams in either direction.
+const UDP_CLIENT_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Fail)]
+pub(crate) enum NetworkError {
+    #[fail(display = "Invalid network mode {}", 0)]
+    InvalidNetworkMode(String),
+    #[fail(display = "{} exited before configuring the network", 0)]
+    SlirpNotReady(String),
+    #[fail(display = "Invalid port mapping {}", 0)]
+    InvalidPortMapping(String),
+}
+
+#[repr(C)]
+struct IfReqFlags {
+    name: [u8; IFNAMSIZ],
+    flags: c_short,
+    padding: [u8; IFREQ_PADDING],
+}
+
+impl IfReqFlags {
+    fn new(interface: &str) -> IfReqFlags {
+        let mut name = [0; IFNAMSIZ];
+        for (i, b) in interface.bytes().enumerate().take(IFNAMSIZ - 1) {
+            name[i] = b;
+        }
+        IfReqFlags {
+            name,
+            flags: 0,
+            padding: [0; IFREQ_PADDING],
+        }
+    }
+}
+
+ioctl_readwrite_bad!(get_interface_flags, SIOCGIFFLAGS, IfReqFlags);
+ioctl_write_ptr_bad!(set_interface_flags, SIOCSIFFLAGS, IfReqFlags);
+
+#[derive(Clone, Copy, Default, PartialEq)]
+pub(crate) enum NetworkMode {
+    #[default]
+    Host,
+    None,
+    Slirp,
+}
+
+impl FromStr for NetworkMode {
+    type Err = NetworkError;
+
+    fn from_str(s: &str) -> Result<NetworkMode, Self::Err> {
+        match s {
+            "host" => Ok(NetworkMode::Host),
+            "none" => Ok(NetworkMode::None),
+            "slirp" => Ok(NetworkMode::Slirp),
+            m => Err(NetworkError::InvalidNetworkMode(m.to_owned())),
+        }
+    }
+}
+
+impl NetworkMode {
+    pub(crate) fn isolated(self) -> bool {
+        self != NetworkMode::Host
+    }
+
+    pub(crate) fn attach(self, pid: Pid) -> Result<Option<Slirp>, Error> {
+        match self {
+            NetworkMode::Slirp => Ok(Some(Slirp::new(pid)?)),
+            _ => Ok(None),
+        }
+    }
+}
+
+pub(crate) fn bring_up_loopback() -> Result<(), Error> {
+    let socket_fd = socket(
+        AddressFamily::Inet,
+        SockType::Datagram,
+        SockFlag::empty(),
+        None,
+    )?;
+    let mut request = IfReqFlags::new(LOOPBACK_INTERFACE);
+    let result = unsafe { get_interface_flags(socket_fd, &mut request) }.and_then(|_| {
+        request.flags |= IFF_UP as c_short;
+        unsafe { set_interface_flags(socket_fd, &request) }
+    });
+    close(socket_fd)?;
+    result?;
+    Ok(())
+}
+
+pub(crate) struct Slirp {
+    child: Child,
+}
+
+// Lets the command about to be executed inherit [fd].
+fn inherit_fd(fd: RawFd) -> io::Result<()> {
+    if unsafe { fcntl(fd, F_SETFD, 0) } < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+// Stopping a helper that already finished, or that was already waited for, isn't an error.
+fn report_stop_failure(helper: &str, result: nix::Result<()>) {
+    match result {
+        Ok(()) | Err(SyscallError::Sys(Errno::ESRCH)) | Err(SyscallError::Sys(Errno::ECHILD)) => {}
+        Err(e) => eprintln!("Failed to stop {}: {}", helper, e),
+    }
+}
+
+impl Slirp {
+    fn new(pid: Pid) -> Result<Slirp, Error> {
+        let (ready_read, ready_write) = pipe2(OFlag::O_CLOEXEC)?;
+        let mut command = Command::new(SLIRP_COMMAND);
+        command
+            .arg("--configure")
+            .arg(SLIRP_MTU)
+            .arg("--disable-host-loopback")
+            .arg(format!("--ready-fd={}", ready_write))
+            .arg(format!("{}", pid))
+            .arg(SLIRP_TAP_INTERFACE);
+        unsafe {
+            command.pre_exec(move || inherit_fd(ready_write));
+        }
+        let spawn_result = command.spawn();
+        close(ready_write)?;
+        let child = match spawn_result {
+            Ok(child) => child,
+            Err(e) => {
+                close(ready_read)?;
+                Err(e)?
+            }
+        };
+        let slirp = Slirp { child };
+        wait_for_ready(ready_read)?;
+        Ok(slirp)
+    }
+}
+
+fn wait_for_ready(ready_fd: RawFd) -> Result<(), Error> {
+    let mut buffer = [0; 1];
+    let read_result = read(ready_fd, &mut buffer);
+    close(ready_fd)?;
+    if read_result? == 0 {
+        Err(NetworkError::SlirpNotReady(SLIRP_COMMAND.to_owned()))?
+    }
+    Ok(())
+}
+
+impl Drop for Slirp {
+    fn drop(&mut self) {
+        match kill(Pid::from_raw(self.child.id() as i32), SIGTERM) {
+            Ok(()) => {
+                if let Err(e) = self.child.wait() {
+                    eprintln!("Failed to stop {}: {}", SLIRP_COMMAND, e);
+                }
+            }
+            result => report_stop_failure(SLIRP_COMMAND, result),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Protocol {
+    Tcp,
+    Udp,
+}
+
+#[derive(Clone)]
+pub(crate) struct PortMapping {
+    host_port: u16,
+    container_port: u16,
+    protocol: Protocol,
+}
+
+impl FromStr for PortMapping {
+    type Err = NetworkError;
+
+    fn from_str(s: &str) -> Result<PortMapping, Self::Err> {
+        let invalid = || NetworkError::InvalidPortMapping(s.to_owned());
+        let (ports, protocol) = match s.split('/').collect::<Vec<&str>>().as_slice() {
+            [ports] => (*ports, Protocol::Tcp),
+            [ports, "tcp"] => (*ports, Protocol::Tcp),
+            [ports, "udp"] => (*ports, Protocol::Udp),
+            _ => Err(invalid())?,
+        };
+        match ports.split(':').collect::<Vec<&str>>().as_slice() {
+            [host_port, container_port] => Ok(PortMapping {
+                host_port: u16::from_str(host_port).map_err(|_| invalid())?,
+                container_port: u16::from_str(container_port).map_err(|_| invalid())?,
+                protocol,
+            }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+enum PublishedPort {
+    Tcp(TcpListener, u16),
+    Udp(UdpSocket, u16),
+}
+
+impl PublishedPort {
+    fn bind(mapping: &PortMapping) -> Result<PublishedPort, Error> {
+        let address = (ANY_ADDRESS, mapping.host_port);
+        Ok(match mapping.protocol {
+            Protocol::Tcp => {
+                PublishedPort::Tcp(TcpListener::bind(address)?, mapping.container_port)
+            }
+            Protocol::Udp => PublishedPort::Udp(UdpSocket::bind(address)?, mapping.container_port),
+        })
+    }
+
+    fn forward(self) -> JoinHandle<Result<(), Error>> {
+        match self {
+            PublishedPort::Tcp(listener, port) => spawn(move || forward_tcp(listener, port)),
+            PublishedPort::Udp(socket, port) => spawn(move || forward_udp(socket, port)),
+        }
+    }
+}
+
+fn proxy_tcp(client: TcpStream, upstream: TcpStream) -> Result<(), Error> {
+    let mut client_reader = client.try_clone()?;
+    let mut upstream_writer = upstream.try_clone()?;
+    let uploader = spawn(move || {
+        let result = copy(&mut client_reader, &mut upstream_writer);
+        upstream_writer.shutdown(Shutdown::Write).ok();
+        result
+    });
+    let mut upstream_reader = upstream;
+    let mut client_writer = client;
+    copy(&mut upstream_reader, &mut client_writer)?;
+    client_writer.shutdown(Shutdown::Write).ok();
+    uploader.join().ok();
+    Ok(())
+}
+
+// A failed connection only drops that client.
+fn forward_tcp(listener: TcpListener, container_port: u16) -> Result<(), Error> {
+    for stream in listener.incoming() {
+        let client = match stream {
+            Ok(client) => client,
+            Err(e) => {
+                eprintln!(
+                    "Failed to accept a connection to port {}: {}",
+                    container_port, e
+                );
+                continue;
+            }
+        };
+        if let Ok(upstream) = TcpStream::connect((LOCALHOST, container_port)) {
+            spawn(move || proxy_tcp(client, upstream));
+        }
+    }
+    Ok(())
+}
+
+#[inline]
+fn is_timeout(error: &io::Error) -> bool {
+    error.kind() == ErrorKind::WouldBlock || error.kind() == ErrorKind::TimedOut
+}
+
+// Finishes once the client was idle for a whole timeout, or the container refused its datagrams.
+fn relay_udp_replies(
+    upstream: UdpSocket,
+    host_socket: UdpSocket,
+    client: SocketAddr,
+    active: Arc<AtomicBool>,
+) -> Result<(), Error> {
+    let mut buffer = [0; UDP_BUFFER_SIZE];
+    upstream.set_read_timeout(Some(UDP_CLIENT_TIMEOUT))?;
+    loop {
+        match upstream.recv(&mut buffer) {
+            Ok(size) => {
+                host_socket.send_to(&buffer[..size], client)?;
+            }
+            Err(ref e) if is_timeout(e) => {
+                if !active.swap(false, Ordering::Relaxed) {
+                    return Ok(());
+                }
+            }
+            Err(e) => Err(e)?,
+        }
+    }
+}
+
+struct UdpClient {
+    upstream: UdpSocket,
+    active: Arc<AtomicBool>,
+    relay: JoinHandle<Result<(), Error>>,
+}
+
+impl UdpClient {
+    fn connect(
+        host_socket: &UdpSocket,
+        client: SocketAddr,
+        container_port: u16,
+    ) -> Result<UdpClient, Error> {
+        let upstream = UdpSocket::bind((LOCALHOST, 0))?;
+        upstream.connect((LOCALHOST, container_port))?;
+        let replies_upstream = upstream.try_clone()?;
+        let replies_socket = host_socket.try_clone()?;
+        let active = Arc::new(AtomicBool::new(true));
+        let replies_active = active.clone();
+        let relay = spawn(move || {
+            relay_udp_replies(replies_upstream, replies_socket, client, replies_active)
+        });
+        Ok(UdpClient {
+            upstream,
+            active,
+            relay,
+        })
+    }
+
+    fn send(&self, datagram: &[u8]) -> bool {
+        self.active.store(true, Ordering::Relaxed);
+        self.upstream.send(datagram).is_ok()
+    }
+}
+
+// Clients get their own socket towards the container, so that replies can be told apart. Clients
+// whose relay finished are forgotten, and a datagram the container refuses drops its client.
+fn forward_udp(host_socket: UdpSocket, container_port: u16) -> Result<(), Error> {
+    let mut clients: HashMap<SocketAddr, UdpClient> = HashMap::new();
+    let mut buffer = [0; UDP_BUFFER_SIZE];
+    host_socket.set_read_timeout(Some(UDP_CLIENT_TIMEOUT))?;
+    loop {
+        let received = host_socket.recv_from(&mut buffer);
+        clients.retain(|_, c| !c.relay.is_finished());
+        let (size, client) = match received {
+            Ok(received) => received,
+            Err(ref e) if is_timeout(e) => continue,
+            Err(e) => Err(e)?,
+        };
+        let upstream = match clients.entry(client) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                match UdpClient::connect(&host_socket, client, container_port) {
+                    Ok(upstream) => entry.insert(upstream),
+                    Err(e) => {
+                        eprintln!("Failed to forward UDP port {}: {}", container_port, e);
+                        continue;
+                    }
+                }
+            }
+        };
+        if !upstream.send(&buffer[..size]) {
+            clients.remove(&client);
+        }
+    }
+}
+
+fn join_network_namespace(pid: Pid) -> Result<(), Error> {
+    for (namespace, flag) in &[
+        ("user", CloneFlags::CLONE_NEWUSER),
+        ("net", CloneFlags::CLONE_NEWNET),
+    ] {
+        let fd = open(
+            format!("/proc/{}/ns/{}", pid, namespace).as_str(),
+            OFlag::O_RDONLY,
+            Mode::empty(),
+        )?;
+        let result = setns(fd, *flag);
+        close(fd)?;
+        result?;
+    }
+    Ok(())
+}
+
+fn serve_published_ports(pid: Pid, published_ports: Vec<PublishedPort>) -> Result<(), Error> {
+    join_network_namespace(pid)?;
+    let forwarders: Vec<JoinHandle<Result<(), Error>>> = published_ports
+        .into_iter()
+        .map(PublishedPort::forward)
+        .collect();
+    for forwarder in forwarders {
+        if let Ok(result) = forwarder.join() {
+            result?;
+        }
+    }
+    Ok(())
+}
+
+pub(crate) struct PortForwarder {
+    pid: Pid,
+}
+
+impl PortForwarder {
+    pub(crate) fn new(pid: Pid, mappings: &[PortMapping]) -> Result<Option<PortForwarder>, Error> {
+        if mappings.is_empty() {
+            return Ok(None);
+        }
+        let published_ports = mappings
+            .iter()
+            .map(PublishedPort::bind)
+            .collect::<Result<Vec<PublishedPort>, Error>>()?;
+        match fork()? {
+            ForkResult::Parent { child } => Ok(Some(PortForwarder { pid: child })),
+            ForkResult::Child => {
+                if let Err(e) = serve_published_ports(pid, published_ports) {
+                    eprintln!("{}", e);
+                    exit(1);
+                }
+                exit(0);
+            }
+        }
+    }
+}
+
+impl Drop for PortForwarder {
+    fn drop(&mut self) {
+        let result = kill(self.pid, SIGTERM).and_then(|_| waitpid(self.pid, None).map(drop));
+        report_stop_failure("the port forwarder", result);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_port_mappings() {
+        let mapping = PortMapping::from_str("8080:80").unwrap();
+        assert_eq!((mapping.host_port, mapping.container_port), (8080, 80));
+        assert!(mapping.protocol == Protocol::Tcp);
+        assert!(PortMapping::from_str("53:5353/tcp").unwrap().protocol == Protocol::Tcp);
+        let mapping = PortMapping::from_str("53:5353/udp").unwrap();
+        assert_eq!((mapping.host_port, mapping.container_port), (53, 5353));
+        assert!(mapping.protocol == Protocol::Udp);
+    }
+
+    #[test]
+    fn rejects_invalid_port_mappings() {
+        for mapping in [
+            "",
+            "80",
+            "8080:",
+            ":80",
+            "8080:80:90",
+            "8080:80/sctp",
+            "8080:80/udp/tcp",
+            "65536:80",
+            "-1:80",
+            "http:80",
+        ]
+        .iter()
+        {
+            assert!(PortMapping::from_str(mapping).is_err(), "{}", mapping);
+        }
+    }
+}
@@ -1,5 +1,5 @@
 use crate::btrfs_send::{BtrfsSend, BtrfsSendCommand, Timespec};
-use crate::images::{btrfs_ioc_send, BtrfsSendArgs, BtrfsSubvolInfo, ImageRepository};
+use crate::images::{btrfs_ioc_send, BtrfsSendArgs, BtrfsSubvolInfo, ImageConfig, ImageRepository};
 use chrono::prelude::Utc;
 use failure::Error;
 use nix::dir::Dir;
@@ -76,6 +76,12 @@ struct LayerJson {
     parent: Option<String>,
 }
 
+#[derive(Deserialize)]
+struct LayerJsonConfig {
+    #[serde(default)]
+    config: Option<ImageConfig>,
+}
+
 #[derive(Deserialize, Clone)]
 struct OCIImageRepositoriesFileLatest {
     latest: String,
@@ -635,9 +641,18 @@ impl OCIImage {
         let latest_content = self.extract_latest_content(&repositories_content)?;
         let mut layer_stack = self.build_layer_stack(latest_content.latest.as_str())?;
         self.import_from_layer_stack(image_repository, &mut layer_stack)?;
+        let config = self.extract_image_config(latest_content.latest.as_str())?;
+        image_repository.save_image_config(self.name.as_str(), &config)?;
         Ok(())
     }
 
+    #[inline]
+    fn extract_image_config(&self, layer: &str) -> Result<ImageConfig, Error> {
+        let file_path = self.tar_content.path().join(layer).join("json");
+        let layer_json = from_str::<LayerJsonConfig>(read_to_string(&file_path)?.as_str())?;
+        Ok(layer_json.config.unwrap_or_default())
+    }
+
     #[inline]
     fn import_from_layer_stack(
         &self,
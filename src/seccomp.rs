@@ -0,0 +1,768 @@
+use crate::capabilities::Capabilities;
+use crate::syscalls::{AUDIT_ARCH, SYSCALLS};
+use failure::Error;
+use nix::libc::{
+    c_ulong, c_ushort, prctl, EPERM, PR_SET_NO_NEW_PRIVS, PR_SET_SECCOMP, SECCOMP_MODE_FILTER,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::from_str;
+use std::env::consts::ARCH;
+use std::fs::read_to_string;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+const UNCONFINED: &str = "unconfined";
+const BPF_LD_W_ABS: u16 = 0x20;
+const BPF_ALU_AND_K: u16 = 0x54;
+const BPF_JMP_JEQ_K: u16 = 0x15;
+const BPF_JMP_JGT_K: u16 = 0x25;
+const BPF_JMP_JGE_K: u16 = 0x35;
+const BPF_RET_K: u16 = 0x06;
+const SECCOMP_DATA_NR: u32 = 0;
+const SECCOMP_DATA_ARCH: u32 = 4;
+const SECCOMP_DATA_ARGS: u32 = 16;
+const SECCOMP_RET_KILL_PROCESS: u32 = 0x8000_0000;
+const SECCOMP_RET_KILL_THREAD: u32 = 0x0000_0000;
+const SECCOMP_RET_TRAP: u32 = 0x0003_0000;
+const SECCOMP_RET_ERRNO: u32 = 0x0005_0000;
+const SECCOMP_RET_TRACE: u32 = 0x7ff0_0000;
+const SECCOMP_RET_LOG: u32 = 0x7ffc_0000;
+const SECCOMP_RET_ALLOW: u32 = 0x7fff_0000;
+const SECCOMP_RET_DATA: u32 = 0x0000_ffff;
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+const MAX_JUMP: usize = 255;
+const DEFAULT_DENIED_SYSCALLS: &[&str] = &[
+    "add_key",
+    "create_module",
+    "get_kernel_syms",
+    "kexec_file_load",
+    "kexec_load",
+    "keyctl",
+    "nfsservctl",
+    "pivot_root",
+    "query_module",
+    "request_key",
+    "swapoff",
+    "swapon",
+    "sysfs",
+    "_sysctl",
+    "uselib",
+    "userfaultfd",
+    "ustat",
+];
+// Denied by the default profile unless the container has the capability, like Docker does.
+const CAPABILITY_DENIED_SYSCALLS: &[(&str, &[&str])] = &[
+    ("CAP_DAC_READ_SEARCH", &["open_by_handle_at"]),
+    (
+        "CAP_SYS_ADMIN",
+        &[
+            "bpf",
+            "fsconfig",
+            "fsmount",
+            "fsopen",
+            "fspick",
+            "lookup_dcookie",
+            "mount",
+            "mount_setattr",
+            "move_mount",
+            "name_to_handle_at",
+            "open_tree",
+            "perf_event_open",
+            "quotactl",
+            "setns",
+            "umount2",
+            "unshare",
+        ],
+    ),
+    ("CAP_SYS_BOOT", &["reboot"]),
+    (
+        "CAP_SYS_MODULE",
+        &["delete_module", "finit_module", "init_module"],
+    ),
+    (
+        "CAP_SYS_NICE",
+        &["get_mempolicy", "mbind", "move_pages", "set_mempolicy"],
+    ),
+    ("CAP_SYS_PACCT", &["acct"]),
+    (
+        "CAP_SYS_PTRACE",
+        &["kcmp", "process_vm_readv", "process_vm_writev", "ptrace"],
+    ),
+    ("CAP_SYS_RAWIO", &["ioperm", "iopl"]),
+    (
+        "CAP_SYS_TIME",
+        &["clock_adjtime", "clock_settime", "settimeofday"],
+    ),
+];
+
+#[derive(Debug, Fail)]
+pub(crate) enum SeccompError {
+    #[fail(display = "Invalid seccomp option {}", 0)]
+    InvalidOption(String),
+    #[fail(display = "Unknown seccomp action {}", 0)]
+    UnknownAction(String),
+    #[fail(display = "Unknown seccomp comparison operator {}", 0)]
+    UnknownOperator(String),
+    #[fail(display = "Invalid syscall argument index {}", 0)]
+    InvalidArgumentIndex(u32),
+    #[fail(display = "Seccomp rule for {} is too long", 0)]
+    RuleTooLong(String),
+    #[fail(display = "Seccomp filtering is not supported on {}", 0)]
+    UnsupportedArchitecture(String),
+}
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub(crate) enum SeccompOption {
+    #[default]
+    Default,
+    Profile(PathBuf),
+    Unconfined,
+}
+
+impl FromStr for SeccompOption {
+    type Err = SeccompError;
+
+    fn from_str(s: &str) -> Result<SeccompOption, Self::Err> {
+        match s {
+            "" => Err(SeccompError::InvalidOption(s.to_owned())),
+            UNCONFINED => Ok(SeccompOption::Unconfined),
+            path => Ok(SeccompOption::Profile(PathBuf::from(path))),
+        }
+    }
+}
+
+impl SeccompOption {
+    pub(crate) fn compile(
+        &self,
+        capabilities: &Capabilities,
+    ) -> Result<Option<SeccompFilter>, Error> {
+        let profile = match self {
+            // Only an explicit profile is an error where there's no syscall table.
+            SeccompOption::Default if SYSCALLS.is_empty() => {
+                eprintln!(
+                    "Warning: seccomp filtering is not supported on {}, the container will run \
+                     unconfined",
+                    ARCH
+                );
+                return Ok(None);
+            }
+            SeccompOption::Default => SeccompProfile::default(),
+            SeccompOption::Profile(path) => from_str(read_to_string(path)?.as_str())?,
+            SeccompOption::Unconfined => return Ok(None),
+        };
+        Ok(Some(profile.compile(capabilities)?))
+    }
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SeccompCondition {
+    #[serde(default)]
+    arches: Vec<String>,
+    #[serde(default)]
+    caps: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SeccompArgument {
+    index: u32,
+    value: u64,
+    #[serde(default)]
+    value_two: u64,
+    op: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SeccompSyscall {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    names: Vec<String>,
+    action: String,
+    #[serde(default)]
+    args: Vec<SeccompArgument>,
+    #[serde(default)]
+    errno_ret: Option<u32>,
+    #[serde(default)]
+    includes: SeccompCondition,
+    #[serde(default)]
+    excludes: SeccompCondition,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SeccompProfile {
+    default_action: String,
+    #[serde(default)]
+    default_errno_ret: Option<u32>,
+    #[serde(default)]
+    syscalls: Vec<SeccompSyscall>,
+}
+
+impl Default for SeccompProfile {
+    fn default() -> SeccompProfile {
+        let mut syscalls = vec![SeccompSyscall::deny(
+            DEFAULT_DENIED_SYSCALLS,
+            SeccompCondition::default(),
+        )];
+        syscalls.extend(
+            CAPABILITY_DENIED_SYSCALLS
+                .iter()
+                .map(|(capability, names)| {
+                    SeccompSyscall::deny(
+                        names,
+                        SeccompCondition {
+                            arches: Vec::new(),
+                            caps: vec![(*capability).to_owned()],
+                        },
+                    )
+                }),
+        );
+        SeccompProfile {
+            default_action: "SCMP_ACT_ALLOW".to_owned(),
+            default_errno_ret: None,
+            syscalls,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SockFilter {
+    code: u16,
+    jt: u8,
+    jf: u8,
+    k: u32,
+}
+
+#[repr(C)]
+struct SockFprog {
+    len: c_ushort,
+    filter: *const SockFilter,
+}
+
+#[derive(Clone, Copy)]
+enum Target {
+    Next(u8),
+    Fail,
+}
+
+#[derive(Clone, Copy)]
+struct Instruction {
+    code: u16,
+    jt: Target,
+    jf: Target,
+    k: u32,
+}
+
+impl Instruction {
+    fn statement(code: u16, k: u32) -> Instruction {
+        Instruction {
+            code,
+            jt: Target::Next(0),
+            jf: Target::Next(0),
+            k,
+        }
+    }
+
+    fn jump(code: u16, k: u32, jt: Target, jf: Target) -> Instruction {
+        Instruction { code, jt, jf, k }
+    }
+}
+
+#[inline]
+fn native_architecture() -> &'static str {
+    if ARCH == "x86_64" {
+        "amd64"
+    } else {
+        ARCH
+    }
+}
+
+fn parse_action(action: &str, errno: u32) -> Result<u32, SeccompError> {
+    match action {
+        "SCMP_ACT_KILL" | "SCMP_ACT_KILL_THREAD" => Ok(SECCOMP_RET_KILL_THREAD),
+        "SCMP_ACT_KILL_PROCESS" => Ok(SECCOMP_RET_KILL_PROCESS),
+        "SCMP_ACT_TRAP" => Ok(SECCOMP_RET_TRAP),
+        "SCMP_ACT_ERRNO" => Ok(SECCOMP_RET_ERRNO | (errno & SECCOMP_RET_DATA)),
+        "SCMP_ACT_TRACE" => Ok(SECCOMP_RET_TRACE | (errno & SECCOMP_RET_DATA)),
+        "SCMP_ACT_LOG" => Ok(SECCOMP_RET_LOG),
+        "SCMP_ACT_ALLOW" => Ok(SECCOMP_RET_ALLOW),
+        a => Err(SeccompError::UnknownAction(a.to_owned())),
+    }
+}
+
+fn compile_argument(argument: &SeccompArgument) -> Result<Vec<Instruction>, SeccompError> {
+    if argument.index > 5 {
+        Err(SeccompError::InvalidArgumentIndex(argument.index))?
+    }
+    let low_offset = SECCOMP_DATA_ARGS + argument.index * 8;
+    let high_offset = low_offset + 4;
+    let high = (argument.value >> 32) as u32;
+    let low = argument.value as u32;
+    let load_high = Instruction::statement(BPF_LD_W_ABS, high_offset);
+    let load_low = Instruction::statement(BPF_LD_W_ABS, low_offset);
+    let next = Target::Next(0);
+    let fail = Target::Fail;
+    Ok(match argument.op.as_str() {
+        "SCMP_CMP_EQ" => vec![
+            load_high,
+            Instruction::jump(BPF_JMP_JEQ_K, high, next, fail),
+            load_low,
+            Instruction::jump(BPF_JMP_JEQ_K, low, next, fail),
+        ],
+        "SCMP_CMP_NE" => vec![
+            load_high,
+            Instruction::jump(BPF_JMP_JEQ_K, high, next, Target::Next(2)),
+            load_low,
+            Instruction::jump(BPF_JMP_JEQ_K, low, fail, next),
+        ],
+        "SCMP_CMP_MASKED_EQ" => {
+            let expected_high = (argument.value_two >> 32) as u32;
+            let expected_low = argument.value_two as u32;
+            vec![
+                load_high,
+                Instruction::statement(BPF_ALU_AND_K, high),
+                Instruction::jump(BPF_JMP_JEQ_K, expected_high, next, fail),
+                load_low,
+                Instruction::statement(BPF_ALU_AND_K, low),
+                Instruction::jump(BPF_JMP_JEQ_K, expected_low, next, fail),
+            ]
+        }
+        "SCMP_CMP_GT" | "SCMP_CMP_GE" => {
+            let low_comparison = if argument.op == "SCMP_CMP_GT" {
+                BPF_JMP_JGT_K
+            } else {
+                BPF_JMP_JGE_K
+            };
+            vec![
+                load_high,
+                Instruction::jump(BPF_JMP_JGT_K, high, Target::Next(3), next),
+                Instruction::jump(BPF_JMP_JEQ_K, high, next, fail),
+                load_low,
+                Instruction::jump(low_comparison, low, next, fail),
+            ]
+        }
+        "SCMP_CMP_LT" | "SCMP_CMP_LE" => {
+            let low_comparison = if argument.op == "SCMP_CMP_LT" {
+                BPF_JMP_JGE_K
+            } else {
+                BPF_JMP_JGT_K
+            };
+            vec![
+                load_high,
+                Instruction::jump(BPF_JMP_JGE_K, high, next, Target::Next(3)),
+                Instruction::jump(BPF_JMP_JEQ_K, high, next, fail),
+                load_low,
+                Instruction::jump(low_comparison, low, fail, next),
+            ]
+        }
+        o => Err(SeccompError::UnknownOperator(o.to_owned()))?,
+    })
+}
+
+fn resolve_target(target: Target, position: usize, block_length: usize) -> u8 {
+    match target {
+        Target::Next(n) => n,
+        Target::Fail => (block_length - position - 1) as u8,
+    }
+}
+
+impl SeccompSyscall {
+    fn deny(names: &[&str], excludes: SeccompCondition) -> SeccompSyscall {
+        SeccompSyscall {
+            name: None,
+            names: names.iter().map(|s| (*s).to_owned()).collect(),
+            action: "SCMP_ACT_ERRNO".to_owned(),
+            args: Vec::new(),
+            errno_ret: Some(EPERM as u32),
+            includes: SeccompCondition::default(),
+            excludes,
+        }
+    }
+
+    fn applies(&self, capabilities: &Capabilities) -> bool {
+        let architecture = native_architecture();
+        let included = self.includes.caps.iter().all(|c| capabilities.contains(c))
+            && (self.includes.arches.is_empty()
+                || self.includes.arches.iter().any(|a| a == architecture));
+        let excluded = self.excludes.caps.iter().any(|c| capabilities.contains(c))
+            || self.excludes.arches.iter().any(|a| a == architecture);
+        included && !excluded
+    }
+
+    fn compile(&self, default_errno: u32) -> Result<Vec<SockFilter>, Error> {
+        let action = parse_action(&self.action, self.errno_ret.unwrap_or(default_errno))?;
+        let mut arguments = Vec::new();
+        for argument in self.args.iter() {
+            arguments.extend(compile_argument(argument)?);
+        }
+        let mut result = Vec::new();
+        for name in self.name.iter().chain(self.names.iter()) {
+            let number = match SYSCALLS.iter().find(|(n, _)| n == name) {
+                Some((_, number)) => *number,
+                None => continue,
+            };
+            let mut block = vec![
+                Instruction::statement(BPF_LD_W_ABS, SECCOMP_DATA_NR),
+                Instruction::jump(BPF_JMP_JEQ_K, number, Target::Next(0), Target::Fail),
+            ];
+            block.extend(arguments.iter().cloned());
+            block.push(Instruction::statement(BPF_RET_K, action));
+            if block.len() > MAX_JUMP {
+                Err(SeccompError::RuleTooLong(name.to_owned()))?
+            }
+            let block_length = block.len();
+            result.extend(block.iter().enumerate().map(|(position, i)| SockFilter {
+                code: i.code,
+                jt: resolve_target(i.jt, position, block_length),
+                jf: resolve_target(i.jf, position, block_length),
+                k: i.k,
+            }));
+        }
+        Ok(result)
+    }
+}
+
+impl SeccompProfile {
+    fn compile(&self, capabilities: &Capabilities) -> Result<SeccompFilter, Error> {
+        if SYSCALLS.is_empty() {
+            Err(SeccompError::UnsupportedArchitecture(ARCH.to_owned()))?
+        }
+        let default_errno = self.default_errno_ret.unwrap_or(EPERM as u32);
+        let default_action = parse_action(&self.default_action, default_errno)?;
+        let mut instructions = vec![
+            SockFilter {
+                code: BPF_LD_W_ABS,
+                jt: 0,
+                jf: 0,
+                k: SECCOMP_DATA_ARCH,
+            },
+            SockFilter {
+                code: BPF_JMP_JEQ_K,
+                jt: 1,
+                jf: 0,
+                k: AUDIT_ARCH,
+            },
+            SockFilter {
+                code: BPF_RET_K,
+                jt: 0,
+                jf: 0,
+                k: SECCOMP_RET_KILL_PROCESS,
+            },
+            SockFilter {
+                code: BPF_LD_W_ABS,
+                jt: 0,
+                jf: 0,
+                k: SECCOMP_DATA_NR,
+            },
+            SockFilter {
+                code: BPF_JMP_JGE_K,
+                jt: 0,
+                jf: 1,
+                k: X32_SYSCALL_BIT,
+            },
+            SockFilter {
+                code: BPF_RET_K,
+                jt: 0,
+                jf: 0,
+                k: SECCOMP_RET_ERRNO | EPERM as u32,
+            },
+        ];
+        for syscall in self.syscalls.iter().filter(|s| s.applies(capabilities)) {
+            instructions.extend(syscall.compile(default_errno)?);
+        }
+        instructions.push(SockFilter {
+            code: BPF_RET_K,
+            jt: 0,
+            jf: 0,
+            k: default_action,
+        });
+        Ok(SeccompFilter { instructions })
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct SeccompFilter {
+    instructions: Vec<SockFilter>,
+}
+
+#[inline]
+fn check_result(result: i32) -> io::Result<()> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+impl SeccompFilter {
+    pub(crate) fn install(&self) -> io::Result<()> {
+        let enable: c_ulong = 1;
+        let program = SockFprog {
+            len: self.instructions.len() as c_ushort,
+            filter: self.instructions.as_ptr(),
+        };
+        check_result(unsafe { prctl(PR_SET_NO_NEW_PRIVS, enable, 0, 0, 0) })?;
+        check_result(unsafe {
+            prctl(
+                PR_SET_SECCOMP,
+                SECCOMP_MODE_FILTER as c_ulong,
+                &program as *const SockFprog,
+            )
+        })
+    }
+}
+
+// Syscall numbers are only known on x86_64.
+#[cfg(all(test, target_arch = "x86_64"))]
+mod tests {
+    use super::*;
+    use crate::capabilities::Capability;
+
+    const ARGUMENT: u64 = 0x1_0000_0005;
+    const VALUES: &[u64] = &[
+        0,
+        5,
+        6,
+        0x1_0000_0000,
+        0x1_0000_0004,
+        ARGUMENT,
+        0x1_0000_0006,
+        0x2_0000_0005,
+        u64::MAX,
+    ];
+
+    // Runs [filter] the way the kernel does for a call of [nr] with [args], walking every jump so
+    // that offsets out of the program fail the test.
+    fn evaluate(filter: &[SockFilter], arch: u32, nr: u32, args: &[u64; 6]) -> u32 {
+        let load = |offset: u32| match offset {
+            SECCOMP_DATA_NR => nr,
+            SECCOMP_DATA_ARCH => arch,
+            o if o >= SECCOMP_DATA_ARGS => {
+                let argument = args[((o - SECCOMP_DATA_ARGS) / 8) as usize];
+                // The low half comes first.
+                if (o - SECCOMP_DATA_ARGS) & 4 == 0 {
+                    argument as u32
+                } else {
+                    (argument >> 32) as u32
+                }
+            }
+            o => panic!("load of unknown offset {}", o),
+        };
+        let mut accumulator = 0;
+        let mut pc = 0;
+        loop {
+            let i = filter[pc];
+            let taken = match i.code {
+                BPF_LD_W_ABS => {
+                    accumulator = load(i.k);
+                    None
+                }
+                BPF_ALU_AND_K => {
+                    accumulator &= i.k;
+                    None
+                }
+                BPF_JMP_JEQ_K => Some(accumulator == i.k),
+                BPF_JMP_JGT_K => Some(accumulator > i.k),
+                BPF_JMP_JGE_K => Some(accumulator >= i.k),
+                BPF_RET_K => return i.k,
+                c => panic!("unknown instruction {:#x}", c),
+            };
+            pc += 1 + match taken {
+                Some(true) => i.jt as usize,
+                Some(false) => i.jf as usize,
+                None => 0,
+            };
+        }
+    }
+
+    fn compile(profile: &str) -> Vec<SockFilter> {
+        from_str::<SeccompProfile>(profile)
+            .unwrap()
+            .compile(&Capabilities::default())
+            .unwrap()
+            .instructions
+    }
+
+    fn syscall(name: &str) -> u32 {
+        SYSCALLS.iter().find(|(n, _)| *n == name).unwrap().1
+    }
+
+    fn compare(op: &str, value_two: u64) -> Vec<SockFilter> {
+        compile(&format!(
+            r#"{{"defaultAction": "SCMP_ACT_ALLOW", "syscalls": [{{"names": ["read"],
+            "action": "SCMP_ACT_ERRNO", "errnoRet": 1, "args": [{{"index": 2,
+            "value": {}, "valueTwo": {}, "op": "{}"}}]}}]}}"#,
+            ARGUMENT, value_two, op
+        ))
+    }
+
+    fn matches(filter: &[SockFilter], value: u64) -> bool {
+        let args = [0, 0, value, 0, 0, 0];
+        evaluate(filter, AUDIT_ARCH, syscall("read"), &args) == SECCOMP_RET_ERRNO | 1
+    }
+
+    fn holds(op: &str, value: u64) -> bool {
+        match op {
+            "SCMP_CMP_EQ" => value == ARGUMENT,
+            "SCMP_CMP_NE" => value != ARGUMENT,
+            "SCMP_CMP_GT" => value > ARGUMENT,
+            "SCMP_CMP_GE" => value >= ARGUMENT,
+            "SCMP_CMP_LT" => value < ARGUMENT,
+            _ => value <= ARGUMENT,
+        }
+    }
+
+    #[test]
+    fn checks_architecture_and_x32_calls() {
+        let filter = compile(r#"{"defaultAction": "SCMP_ACT_ALLOW"}"#);
+        let args = [0; 6];
+        assert_eq!(filter[1].k, AUDIT_ARCH);
+        assert_eq!(
+            evaluate(&filter, !AUDIT_ARCH, 0, &args),
+            SECCOMP_RET_KILL_PROCESS
+        );
+        assert_eq!(
+            evaluate(&filter, AUDIT_ARCH, X32_SYSCALL_BIT | 1, &args),
+            SECCOMP_RET_ERRNO | EPERM as u32
+        );
+        assert_eq!(evaluate(&filter, AUDIT_ARCH, 1, &args), SECCOMP_RET_ALLOW);
+    }
+
+    #[test]
+    fn default_profile_denies_listed_calls() {
+        let filter = SeccompProfile::default()
+            .compile(&Capabilities::default())
+            .unwrap()
+            .instructions;
+        let args = [0; 6];
+        for name in ["mount", "ptrace", "keyctl"].iter() {
+            assert_eq!(
+                evaluate(&filter, AUDIT_ARCH, syscall(name), &args),
+                SECCOMP_RET_ERRNO | EPERM as u32
+            );
+        }
+        assert_eq!(
+            evaluate(&filter, AUDIT_ARCH, syscall("read"), &args),
+            SECCOMP_RET_ALLOW
+        );
+    }
+
+    #[test]
+    fn default_profile_allows_calls_with_their_capability() {
+        let args = [0; 6];
+        let denied = SECCOMP_RET_ERRNO | EPERM as u32;
+        let sys_admin = Capability::from_str("SYS_ADMIN").unwrap();
+        for (capabilities, expected) in [
+            (Capabilities::default(), denied),
+            (Capabilities::new(&[sys_admin], &[]), SECCOMP_RET_ALLOW),
+        ]
+        .iter()
+        {
+            let filter = SeccompProfile::default()
+                .compile(capabilities)
+                .unwrap()
+                .instructions;
+            for name in ["setns", "unshare", "mount"].iter() {
+                assert_eq!(
+                    evaluate(&filter, AUDIT_ARCH, syscall(name), &args),
+                    *expected
+                );
+            }
+            assert_eq!(
+                evaluate(&filter, AUDIT_ARCH, syscall("mbind"), &args),
+                denied
+            );
+            assert_eq!(
+                evaluate(&filter, AUDIT_ARCH, syscall("keyctl"), &args),
+                denied
+            );
+        }
+    }
+
+    #[test]
+    fn failed_rules_fall_through_to_the_next_one() {
+        let filter = compile(
+            r#"{"defaultAction": "SCMP_ACT_ERRNO", "defaultErrnoRet": 3, "syscalls": [
+            {"names": ["read", "write"], "action": "SCMP_ACT_ALLOW", "args": [
+                {"index": 0, "value": 1, "op": "SCMP_CMP_EQ"},
+                {"index": 1, "value": 2, "op": "SCMP_CMP_LE"}]},
+            {"name": "write", "action": "SCMP_ACT_ERRNO", "errnoRet": 2}]}"#,
+        );
+        let read = syscall("read");
+        let write = syscall("write");
+        assert_eq!(
+            evaluate(&filter, AUDIT_ARCH, read, &[1, 2, 0, 0, 0, 0]),
+            SECCOMP_RET_ALLOW
+        );
+        assert_eq!(
+            evaluate(&filter, AUDIT_ARCH, write, &[1, 0, 0, 0, 0, 0]),
+            SECCOMP_RET_ALLOW
+        );
+        assert_eq!(
+            evaluate(&filter, AUDIT_ARCH, read, &[1, 3, 0, 0, 0, 0]),
+            SECCOMP_RET_ERRNO | 3
+        );
+        assert_eq!(
+            evaluate(&filter, AUDIT_ARCH, write, &[0, 2, 0, 0, 0, 0]),
+            SECCOMP_RET_ERRNO | 2
+        );
+        assert_eq!(
+            evaluate(&filter, AUDIT_ARCH, syscall("close"), &[1, 2, 0, 0, 0, 0]),
+            SECCOMP_RET_ERRNO | 3
+        );
+    }
+
+    #[test]
+    fn compares_64_bit_arguments() {
+        let comparisons = [
+            "SCMP_CMP_EQ",
+            "SCMP_CMP_NE",
+            "SCMP_CMP_GT",
+            "SCMP_CMP_GE",
+            "SCMP_CMP_LT",
+            "SCMP_CMP_LE",
+        ];
+        for op in comparisons.iter() {
+            let filter = compare(op, 0);
+            for value in VALUES.iter() {
+                assert_eq!(
+                    matches(&filter, *value),
+                    holds(op, *value),
+                    "{} {:#x}",
+                    op,
+                    value
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn compares_masked_arguments() {
+        let filter = compare("SCMP_CMP_MASKED_EQ", 0x1_0000_0004);
+        for value in VALUES.iter() {
+            assert_eq!(
+                matches(&filter, *value),
+                value & ARGUMENT == 0x1_0000_0004,
+                "{:#x}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_arguments() {
+        let argument = |index: u32, op: &str| SeccompArgument {
+            index,
+            value: 0,
+            value_two: 0,
+            op: op.to_owned(),
+        };
+        assert!(compile_argument(&argument(6, "SCMP_CMP_EQ")).is_err());
+        assert!(compile_argument(&argument(0, "SCMP_CMP_IN")).is_err());
+    }
+}
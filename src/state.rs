@@ -0,0 +1,431 @@
+use crate::jail::Confinement;
+use chrono::{DateTime, SecondsFormat, Utc};
+use dirs::home_dir;
+use failure::Error;
+use nix::fcntl::{flock, FlockArg};
+use nix::unistd::Pid;
+use serde::{Deserialize, Serialize};
+use serde_json::{from_str, to_string};
+use std::fs::{
+    create_dir_all, read_dir, read_to_string, remove_dir_all, rename, write, File, OpenOptions,
+};
+use std::mem::discriminant;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+const STATE_LOCATION: &str = ".local/lib/ruthless/containers";
+const STATE_FILE: &str = "state.json";
+const TEMPORARY_STATE_FILE: &str = "state.json.tmp";
+const LOCK_FILE: &str = "state.lock";
+const SHORT_ID_LENGTH: usize = 12;
+const SHORT_COMMAND_LENGTH: usize = 20;
+const COLUMN_SEPARATOR: &str = "   ";
+
+#[derive(Debug, Fail)]
+pub(crate) enum StateError {
+    #[fail(display = "No home directory")]
+    NoHomeDirectory,
+    #[fail(display = "Invalid filter {}", 0)]
+    InvalidFilter(String),
+    #[fail(display = "Invalid format {}", 0)]
+    InvalidFormat(String),
+    #[fail(
+        display = "The name {} is in use by another container, which has to exit or be deleted first",
+        0
+    )]
+    NameInUse(String),
+}
+
+// Everything ruthless keeps about a container on the host lives in its own folder, out of the
+// reach of the container and of its image.
+pub(crate) fn container_state_path(container: &str) -> Result<PathBuf, Error> {
+    let home_path = home_dir().ok_or(StateError::NoHomeDirectory)?;
+    Ok(home_path.join(STATE_LOCATION).join(container))
+}
+
+#[inline]
+fn now() -> String {
+    Utc::now().to_rfc3339_opts(SecondsFormat::Nanos, true)
+}
+
+// Like "5 minutes", for the time elapsed since [time].
+fn elapsed(time: &str) -> String {
+    let seconds = DateTime::parse_from_rfc3339(time)
+        .map(|t| (Utc::now() - t.with_timezone(&Utc)).num_seconds())
+        .unwrap_or_default();
+    let (amount, unit) = match seconds {
+        s if s < 60 => (s, "second"),
+        s if s < 60 * 60 => (s / 60, "minute"),
+        s if s < 24 * 60 * 60 => (s / (60 * 60), "hour"),
+        s => (s / (24 * 60 * 60), "day"),
+    };
+    format!("{} {}{}", amount, unit, if amount == 1 { "" } else { "s" })
+}
+
+#[derive(Clone, Copy, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Status {
+    Created,
+    Running,
+    Paused,
+    Exited,
+}
+
+impl Status {
+    fn name(self) -> &'static str {
+        match self {
+            Status::Created => "created",
+            Status::Running => "running",
+            Status::Paused => "paused",
+            Status::Exited => "exited",
+        }
+    }
+
+    // Whether the container has processes, running or not.
+    pub(crate) fn is_up(self) -> bool {
+        self == Status::Running || self == Status::Paused
+    }
+}
+
+// The record of a container, written when it's created and updated as it starts and finishes.
+// [options] are the arguments of ruthless run before the image, and [resources] the resource
+// options of ruthless container update that replaced them since. Records written before
+// [confinement] existed get the default one.
+#[derive(Deserialize, Serialize)]
+pub(crate) struct ContainerState {
+    pub(crate) id: String,
+    pub(crate) name: String,
+    pub(crate) image: String,
+    pub(crate) command: Vec<String>,
+    pub(crate) options: Vec<String>,
+    #[serde(default)]
+    pub(crate) resources: Vec<String>,
+    #[serde(default)]
+    pub(crate) confinement: Confinement,
+    pub(crate) pid: Option<i32>,
+    pub(crate) status: Status,
+    pub(crate) created: String,
+    pub(crate) started: Option<String>,
+    pub(crate) finished: Option<String>,
+    pub(crate) exit_code: Option<isize>,
+}
+
+impl ContainerState {
+    pub(crate) fn new(
+        id: String,
+        name: String,
+        image: String,
+        command: Vec<String>,
+        options: Vec<String>,
+    ) -> ContainerState {
+        ContainerState {
+            id,
+            name,
+            image,
+            command,
+            options,
+            resources: vec![],
+            confinement: Confinement::default(),
+            pid: None,
+            status: Status::Created,
+            created: now(),
+            started: None,
+            finished: None,
+            exit_code: None,
+        }
+    }
+
+    pub(crate) fn load(container: &str) -> Result<Option<ContainerState>, Error> {
+        let path = container_state_path(container)?.join(STATE_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(from_str(&read_to_string(path)?)?))
+    }
+
+    // Written to a temporary file first, so that readers never see a partial record.
+    pub(crate) fn save(&self) -> Result<(), Error> {
+        let folder = container_state_path(&self.name)?;
+        create_dir_all(&folder)?;
+        write(folder.join(TEMPORARY_STATE_FILE), to_string(self)?)?;
+        rename(folder.join(TEMPORARY_STATE_FILE), folder.join(STATE_FILE))?;
+        Ok(())
+    }
+
+    pub(crate) fn start(&mut self, pid: Pid) {
+        self.pid = Some(pid.as_raw());
+        self.status = Status::Running;
+        self.started = Some(now());
+    }
+
+    // A container that finished stays so.
+    pub(crate) fn pause(&mut self, paused: bool) {
+        if !self.status.is_up() {
+            return;
+        }
+        self.status = if paused {
+            Status::Paused
+        } else {
+            Status::Running
+        };
+    }
+
+    // Keeps only the latest value of every resource option, like --memory-max=.
+    pub(crate) fn update_resources(&mut self, resources: &[String]) {
+        for resource in resources {
+            let option = &resource[..resource.find('=').map_or(resource.len(), |i| i + 1)];
+            self.resources.retain(|r| !r.starts_with(option));
+            self.resources.push(resource.clone());
+        }
+    }
+
+    pub(crate) fn finish(&mut self, exit_code: isize) {
+        self.pid = None;
+        self.status = Status::Exited;
+        self.finished = Some(now());
+        self.exit_code = Some(exit_code);
+    }
+
+    fn short_id(&self) -> &str {
+        &self.id[..self.id.len().min(SHORT_ID_LENGTH)]
+    }
+
+    fn short_command(&self) -> String {
+        let command = self.command.join(" ");
+        if command.chars().count() > SHORT_COMMAND_LENGTH {
+            let prefix = command
+                .chars()
+                .take(SHORT_COMMAND_LENGTH - 1)
+                .collect::<String>();
+            format!("\"{}…\"", prefix)
+        } else {
+            format!("\"{}\"", command)
+        }
+    }
+
+    fn status_description(&self) -> String {
+        match self.status {
+            Status::Created => "Created".to_owned(),
+            Status::Running => format!(
+                "Up {}",
+                elapsed(self.started.as_ref().unwrap_or(&self.created))
+            ),
+            Status::Paused => format!(
+                "Up {} (Paused)",
+                elapsed(self.started.as_ref().unwrap_or(&self.created))
+            ),
+            Status::Exited => format!(
+                "Exited ({}) {} ago",
+                self.exit_code
+                    .map_or_else(|| "unknown".to_owned(), |c| c.to_string()),
+                elapsed(self.finished.as_ref().unwrap_or(&self.created))
+            ),
+        }
+    }
+}
+
+// Held around every read-modify-write of a record, as the monitor of a container and the commands
+// of ruthless change it concurrently. Closing the file releases the lock.
+struct StateLock {
+    _file: File,
+}
+
+impl StateLock {
+    fn acquire(folder: &Path) -> Result<StateLock, Error> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(folder.join(LOCK_FILE))?;
+        flock(file.as_raw_fd(), FlockArg::LockExclusive)?;
+        Ok(StateLock { _file: file })
+    }
+}
+
+fn change_state<P, F>(container: &str, applies: P, change: F) -> Result<(), Error>
+where
+    P: FnOnce(&ContainerState) -> bool,
+    F: FnOnce(&mut ContainerState),
+{
+    let folder = container_state_path(container)?;
+    if !folder.exists() {
+        return Ok(());
+    }
+    let _lock = StateLock::acquire(&folder)?;
+    if let Some(mut state) = ContainerState::load(container)?.filter(applies) {
+        change(&mut state);
+        state.save()?;
+    }
+    Ok(())
+}
+
+// Applies [change] to the record of [container], unless it was deleted in the meantime.
+pub(crate) fn update_state<F: FnOnce(&mut ContainerState)>(
+    container: &str,
+    change: F,
+) -> Result<(), Error> {
+    change_state(container, |_| true, change)
+}
+
+// Like update_state, but only while the record still belongs to the container with [id], so that
+// a run that lost the name to another container leaves the record of that one alone.
+pub(crate) fn update_own_state<F: FnOnce(&mut ContainerState)>(
+    container: &str,
+    id: &str,
+    change: F,
+) -> Result<(), Error> {
+    change_state(container, |s| s.id == id, change)
+}
+
+pub(crate) fn get_container_states() -> Result<Vec<ContainerState>, Error> {
+    let home_path = home_dir().ok_or(StateError::NoHomeDirectory)?;
+    let location = home_path.join(STATE_LOCATION);
+    if !location.exists() {
+        return Ok(vec![]);
+    }
+    let mut states = vec![];
+    for entry in read_dir(location)? {
+        let name = entry?.file_name().to_string_lossy().into_owned();
+        if let Some(state) = ContainerState::load(&name)? {
+            states.push(state);
+        }
+    }
+    states.sort_by(|a, b| b.created.cmp(&a.created));
+    Ok(states)
+}
+
+pub(crate) fn delete_container_state(container: &str) -> Result<(), Error> {
+    let folder = container_state_path(container)?;
+    if folder.exists() {
+        remove_dir_all(folder)?;
+    }
+    Ok(())
+}
+
+// Filters with the same key match when any of them does, and filters with different keys when all
+// of them do.
+#[derive(Clone)]
+pub(crate) enum Filter {
+    Id(String),
+    Name(String),
+    Image(String),
+    Status(String),
+}
+
+impl FromStr for Filter {
+    type Err = StateError;
+
+    fn from_str(s: &str) -> Result<Filter, Self::Err> {
+        let invalid = || StateError::InvalidFilter(s.to_owned());
+        let i = s.find('=').ok_or_else(invalid)?;
+        let value = s[i + 1..].to_owned();
+        match &s[..i] {
+            "id" => Ok(Filter::Id(value)),
+            "name" => Ok(Filter::Name(value)),
+            "image" => Ok(Filter::Image(value)),
+            "status" => Ok(Filter::Status(value)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl Filter {
+    fn matches(&self, state: &ContainerState) -> bool {
+        match self {
+            Filter::Id(id) => state.id.starts_with(id.as_str()),
+            Filter::Name(name) => state.name.contains(name.as_str()),
+            Filter::Image(image) => state.image == *image,
+            Filter::Status(status) => state.status.name() == status,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq)]
+pub(crate) enum ListFormat {
+    #[default]
+    Table,
+    Json,
+}
+
+impl FromStr for ListFormat {
+    type Err = StateError;
+
+    fn from_str(s: &str) -> Result<ListFormat, Self::Err> {
+        match s {
+            "table" => Ok(ListFormat::Table),
+            "json" => Ok(ListFormat::Json),
+            f => Err(StateError::InvalidFormat(f.to_owned())),
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct ListOptions {
+    pub(crate) all: bool,
+    pub(crate) filters: Vec<Filter>,
+    pub(crate) format: ListFormat,
+}
+
+impl ListOptions {
+    pub(crate) fn matches(&self, state: &ContainerState) -> bool {
+        (self.all || state.status.is_up())
+            && self.filters.iter().all(|filter| {
+                self.filters
+                    .iter()
+                    .filter(|f| discriminant(*f) == discriminant(filter))
+                    .any(|f| f.matches(state))
+            })
+    }
+}
+
+pub(crate) fn print_container_states(
+    states: &[ContainerState],
+    format: ListFormat,
+) -> Result<(), Error> {
+    if format == ListFormat::Json {
+        for state in states {
+            println!("{}", to_string(state)?);
+        }
+        return Ok(());
+    }
+    let mut rows = vec![vec![
+        "CONTAINER ID".to_owned(),
+        "IMAGE".to_owned(),
+        "COMMAND".to_owned(),
+        "CREATED".to_owned(),
+        "STATUS".to_owned(),
+        "NAME".to_owned(),
+    ]];
+    for state in states {
+        rows.push(vec![
+            state.short_id().to_owned(),
+            state.image.clone(),
+            state.short_command(),
+            format!("{} ago", elapsed(&state.created)),
+            state.status_description(),
+            state.name.clone(),
+        ]);
+    }
+    print_table(&rows);
+    Ok(())
+}
+
+// Pads every column to its widest cell.
+pub(crate) fn print_table(rows: &[Vec<String>]) {
+    let mut widths = vec![0; rows.first().map_or(0, |r| r.len())];
+    for row in rows.iter() {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    for row in rows.iter() {
+        let line = row
+            .iter()
+            .zip(widths.iter())
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect::<Vec<String>>()
+            .join(COLUMN_SEPARATOR);
+        println!("{}", line.trim_end());
+    }
+}
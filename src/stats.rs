@@ -0,0 +1,250 @@
+use crate::cgroup::{get_active_cgroups, get_container_cgroup_path};
+use crate::exec::ExecError;
+use crate::state::{print_table, ListFormat};
+use failure::Error;
+use serde::Serialize;
+use serde_json::to_string;
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::path::Path;
+use std::str::FromStr;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+const CLEAR_SCREEN: &str = "\x1b[2J\x1b[H";
+const SIZE_UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB"];
+const UNLIMITED: &str = "max";
+
+#[derive(Default)]
+pub(crate) struct StatsOptions {
+    pub(crate) no_stream: bool,
+    pub(crate) format: ListFormat,
+}
+
+fn read_number(cgroup: &Path, file: &str) -> Option<u64> {
+    u64::from_str(read_to_string(cgroup.join(file)).ok()?.trim()).ok()
+}
+
+// For flat keyed files like cpu.stat and memory.stat, with a "[key] [value]" line per counter.
+fn read_key(cgroup: &Path, file: &str, key: &str) -> Option<u64> {
+    read_to_string(cgroup.join(file))
+        .ok()?
+        .lines()
+        .find_map(|line| {
+            let mut fields = line.split_whitespace();
+            if fields.next()? == key {
+                u64::from_str(fields.next()?).ok()
+            } else {
+                None
+            }
+        })
+}
+
+// io.stat has a line per device like "8:0 rbytes=1 wbytes=2 rios=3 wios=4 dbytes=0 dios=0". The
+// bytes read and written are added up for every device.
+fn read_io(cgroup: &Path) -> (u64, u64) {
+    let mut read = 0;
+    let mut written = 0;
+    let io = read_to_string(cgroup.join("io.stat")).unwrap_or_default();
+    for field in io.split_whitespace() {
+        if let Some(bytes) = field.strip_prefix("rbytes=") {
+            read += u64::from_str(bytes).unwrap_or_default();
+        } else if let Some(bytes) = field.strip_prefix("wbytes=") {
+            written += u64::from_str(bytes).unwrap_or_default();
+        }
+    }
+    (read, written)
+}
+
+// The counters of the cgroup of a container at some point. Files of controllers that aren't
+// enabled for the container read as zero or none.
+struct Sample {
+    time: Instant,
+    cpu_usage: u64,
+    memory_current: u64,
+    memory_peak: Option<u64>,
+    memory_limit: Option<u64>,
+    inactive_file: u64,
+    file: u64,
+    io_read: u64,
+    io_write: u64,
+    pids: u64,
+}
+
+impl Sample {
+    // None once the container finished.
+    fn take(container: &str) -> Result<Option<Sample>, Error> {
+        let cgroup = get_container_cgroup_path(container)?;
+        if !cgroup.exists() {
+            return Ok(None);
+        }
+        let (io_read, io_write) = read_io(&cgroup);
+        Ok(Some(Sample {
+            time: Instant::now(),
+            cpu_usage: read_key(&cgroup, "cpu.stat", "usage_usec").unwrap_or_default(),
+            memory_current: read_number(&cgroup, "memory.current").unwrap_or_default(),
+            memory_peak: read_number(&cgroup, "memory.peak"),
+            // Unlimited memory reads as "max".
+            memory_limit: read_number(&cgroup, "memory.max"),
+            inactive_file: read_key(&cgroup, "memory.stat", "inactive_file").unwrap_or_default(),
+            file: read_key(&cgroup, "memory.stat", "file").unwrap_or_default(),
+            io_read,
+            io_write,
+            pids: read_number(&cgroup, "pids.current").unwrap_or_default(),
+        }))
+    }
+}
+
+// The usage of a container between two samples. The CPU percentage is relative to a single CPU,
+// and the memory usage leaves out the page cache that can be reclaimed right away. Rates are in
+// bytes per second.
+#[derive(Serialize)]
+struct ContainerStats {
+    name: String,
+    cpu_percent: f64,
+    memory_usage: u64,
+    memory_limit: Option<u64>,
+    memory_percent: Option<f64>,
+    memory_peak: Option<u64>,
+    memory_cache: u64,
+    io_read_bytes: u64,
+    io_write_bytes: u64,
+    io_read_rate: f64,
+    io_write_rate: f64,
+    pids: u64,
+}
+
+impl ContainerStats {
+    fn new(name: &str, previous: &Sample, current: &Sample) -> ContainerStats {
+        let elapsed = (current.time - previous.time)
+            .as_secs_f64()
+            .max(f64::EPSILON);
+        let rate = |before: u64, after: u64| after.saturating_sub(before) as f64 / elapsed;
+        let memory_usage = current.memory_current.saturating_sub(current.inactive_file);
+        ContainerStats {
+            name: name.to_owned(),
+            cpu_percent: rate(previous.cpu_usage, current.cpu_usage) / 1e6 * 100.0,
+            memory_usage,
+            memory_limit: current.memory_limit,
+            memory_percent: current
+                .memory_limit
+                .filter(|l| *l > 0)
+                .map(|l| memory_usage as f64 / l as f64 * 100.0),
+            memory_peak: current.memory_peak,
+            memory_cache: current.file,
+            io_read_bytes: current.io_read,
+            io_write_bytes: current.io_write,
+            io_read_rate: rate(previous.io_read, current.io_read),
+            io_write_rate: rate(previous.io_write, current.io_write),
+            pids: current.pids,
+        }
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.name.clone(),
+            format!("{:.2}%", self.cpu_percent),
+            format!(
+                "{} / {}",
+                human_size(self.memory_usage as f64),
+                self.memory_limit
+                    .map_or_else(|| UNLIMITED.to_owned(), |l| human_size(l as f64))
+            ),
+            self.memory_percent
+                .map_or_else(|| "--".to_owned(), |p| format!("{:.2}%", p)),
+            self.memory_peak
+                .map_or_else(|| "--".to_owned(), |p| human_size(p as f64)),
+            format!(
+                "{} / {}",
+                human_size(self.io_read_bytes as f64),
+                human_size(self.io_write_bytes as f64)
+            ),
+            format!(
+                "{}/s / {}/s",
+                human_size(self.io_read_rate),
+                human_size(self.io_write_rate)
+            ),
+            self.pids.to_string(),
+        ]
+    }
+}
+
+// Like 1.50MiB.
+fn human_size(bytes: f64) -> String {
+    let mut size = bytes;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < SIZE_UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{}{}", size as u64, SIZE_UNITS[0])
+    } else {
+        format!("{:.2}{}", size, SIZE_UNITS[unit])
+    }
+}
+
+fn print_stats(stats: &[ContainerStats], options: &StatsOptions) -> Result<(), Error> {
+    if options.format == ListFormat::Json {
+        for s in stats {
+            println!("{}", to_string(s)?);
+        }
+        return Ok(());
+    }
+    if !options.no_stream {
+        print!("{}", CLEAR_SCREEN);
+    }
+    let mut rows = vec![vec![
+        "NAME".to_owned(),
+        "CPU %".to_owned(),
+        "MEM USAGE / LIMIT".to_owned(),
+        "MEM %".to_owned(),
+        "MEM PEAK".to_owned(),
+        "BLOCK I/O".to_owned(),
+        "I/O RATE".to_owned(),
+        "PIDS".to_owned(),
+    ]];
+    rows.extend(stats.iter().map(ContainerStats::row));
+    print_table(&rows);
+    Ok(())
+}
+
+// Samples the given [containers], or every running one when there are none, once per interval.
+// Rates need two samples, so even a single snapshot waits for one interval.
+pub(crate) fn show_stats(containers: &[String], options: &StatsOptions) -> Result<(), Error> {
+    let active = get_active_cgroups()?;
+    for container in containers {
+        if !active.contains(container) {
+            Err(ExecError::ContainerNotRunning(container.to_owned()))?
+        }
+    }
+    let mut previous: HashMap<String, Sample> = HashMap::new();
+    loop {
+        let names = if containers.is_empty() {
+            let mut active = get_active_cgroups()?;
+            active.sort();
+            active
+        } else {
+            containers.to_vec()
+        };
+        let mut samples = HashMap::new();
+        let mut stats = vec![];
+        for name in names {
+            if let Some(sample) = Sample::take(&name)? {
+                if let Some(before) = previous.get(&name) {
+                    stats.push(ContainerStats::new(&name, before, &sample));
+                }
+                samples.insert(name, sample);
+            }
+        }
+        if !previous.is_empty() || samples.is_empty() {
+            print_stats(&stats, options)?;
+            if options.no_stream || (!containers.is_empty() && samples.is_empty()) {
+                return Ok(());
+            }
+        }
+        previous = samples;
+        sleep(SAMPLE_INTERVAL);
+    }
+}
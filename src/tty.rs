@@ -0,0 +1,178 @@
+use failure::Error;
+use nix::errno::Errno;
+use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+use nix::libc::{STDIN_FILENO, STDOUT_FILENO, TIOCGWINSZ, TIOCSCTTY, TIOCSWINSZ};
+use nix::poll::{poll, EventFlags, PollFd};
+use nix::pty::{openpty, Winsize};
+use nix::sys::signal::{SigSet, Signal};
+use nix::sys::signalfd::SignalFd;
+use nix::sys::termios::{cfmakeraw, tcgetattr, tcsetattr, SetArg, Termios};
+use nix::unistd::{close, dup, isatty, read, setsid, write};
+use nix::Error as SyscallError;
+use std::io;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::process::Stdio;
+
+const BUFFER_SIZE: usize = 4096;
+const END_OF_TRANSMISSION: u8 = 4;
+pub(crate) const NO_TIMEOUT: i32 = -1;
+
+ioctl_read_bad!(get_window_size, TIOCGWINSZ, Winsize);
+ioctl_write_ptr_bad!(set_window_size, TIOCSWINSZ, Winsize);
+ioctl_write_int_bad!(set_controlling_terminal, TIOCSCTTY);
+
+pub(crate) fn window_size(fd: RawFd) -> Option<Winsize> {
+    let mut size = Winsize {
+        ws_row: 0,
+        ws_col: 0,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    unsafe { get_window_size(fd, &mut size) }.ok()?;
+    Some(size)
+}
+
+pub(crate) fn write_all(fd: RawFd, mut buffer: &[u8]) -> Result<(), Error> {
+    while !buffer.is_empty() {
+        let written = write(fd, buffer)?;
+        buffer = &buffer[written..];
+    }
+    Ok(())
+}
+
+#[inline]
+pub(crate) fn is_ready(fd: &PollFd) -> bool {
+    fd.revents().is_some_and(|e| {
+        e.intersects(EventFlags::POLLIN | EventFlags::POLLHUP | EventFlags::POLLERR)
+    })
+}
+
+// Puts a terminal in raw mode, so that keys like ^C reach the container instead of being turned
+// into signals for ruthless, and restores it when dropped.
+pub(crate) struct RawTerminal {
+    fd: RawFd,
+    original: Termios,
+}
+
+impl RawTerminal {
+    pub(crate) fn new(fd: RawFd) -> Result<Option<RawTerminal>, Error> {
+        if !isatty(fd)? {
+            return Ok(None);
+        }
+        let original = tcgetattr(fd)?;
+        let mut raw = original.clone();
+        cfmakeraw(&mut raw);
+        tcsetattr(fd, SetArg::TCSANOW, &raw)?;
+        Ok(Some(RawTerminal { fd, original }))
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        tcsetattr(self.fd, SetArg::TCSANOW, &self.original).unwrap();
+    }
+}
+
+// A pseudo-terminal pair. The master stays with ruthless, which relays it to its own terminal,
+// and the slave becomes the controlling terminal of the payload.
+#[derive(Clone, Copy)]
+pub(crate) struct Pty {
+    master: RawFd,
+    slave: RawFd,
+}
+
+impl Pty {
+    pub(crate) fn open() -> Result<Pty, Error> {
+        let pty = openpty(window_size(STDIN_FILENO).as_ref(), None::<&Termios>)?;
+        for fd in [pty.master, pty.slave].iter() {
+            fcntl(*fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+        }
+        Ok(Pty {
+            master: pty.master,
+            slave: pty.slave,
+        })
+    }
+
+    pub(crate) fn close_master(&self) -> Result<(), Error> {
+        close(self.master)?;
+        Ok(())
+    }
+
+    pub(crate) fn close_slave(&self) -> Result<(), Error> {
+        close(self.slave)?;
+        Ok(())
+    }
+
+    pub(crate) fn master(&self) -> RawFd {
+        self.master
+    }
+
+    pub(crate) fn resize(&self, size: &Winsize) -> Result<(), Error> {
+        unsafe { set_window_size(self.master, size) }?;
+        Ok(())
+    }
+
+    pub(crate) fn slave_stdio(&self) -> Result<Stdio, Error> {
+        Ok(unsafe { Stdio::from_raw_fd(dup(self.slave)?) })
+    }
+
+    // Runs between fork and exec of the payload, once the slave is its standard input.
+    pub(crate) fn prepare_payload() -> io::Result<()> {
+        setsid().map_err(|_| io::Error::last_os_error())?;
+        unsafe { set_controlling_terminal(STDIN_FILENO, 0) }
+            .map_err(|_| io::Error::last_os_error())?;
+        Ok(())
+    }
+
+    // Copies the output of the container to the standard output and, when [interactive], the
+    // standard input to the container until every end of the slave is closed. Window size changes
+    // of the terminal are propagated to the pty.
+    pub(crate) fn relay(&self, interactive: bool) -> Result<(), Error> {
+        let mut resize_signals = SigSet::empty();
+        resize_signals.add(Signal::SIGWINCH);
+        resize_signals.thread_block()?;
+        let mut resize = SignalFd::new(&resize_signals)?;
+        let _raw_terminal = if interactive {
+            RawTerminal::new(STDIN_FILENO)?
+        } else {
+            None
+        };
+        let mut stdin_open = interactive;
+        let mut buffer = [0; BUFFER_SIZE];
+        loop {
+            let mut fds = vec![
+                PollFd::new(self.master, EventFlags::POLLIN),
+                PollFd::new(resize.as_raw_fd(), EventFlags::POLLIN),
+            ];
+            if stdin_open {
+                fds.push(PollFd::new(STDIN_FILENO, EventFlags::POLLIN));
+            }
+            match poll(&mut fds, NO_TIMEOUT) {
+                Err(SyscallError::Sys(Errno::EINTR)) => continue,
+                result => result?,
+            };
+            if is_ready(&fds[0]) {
+                match read(self.master, &mut buffer) {
+                    Ok(0) | Err(SyscallError::Sys(Errno::EIO)) => return Ok(()),
+                    Ok(n) => write_all(STDOUT_FILENO, &buffer[..n])?,
+                    Err(e) => Err(e)?,
+                }
+            }
+            if is_ready(&fds[1]) {
+                resize.read_signal()?;
+                if let Some(size) = window_size(STDIN_FILENO) {
+                    self.resize(&size)?;
+                }
+            }
+            if stdin_open && is_ready(&fds[2]) {
+                match read(STDIN_FILENO, &mut buffer)? {
+                    0 => {
+                        write_all(self.master, &[END_OF_TRANSMISSION])?;
+                        stdin_open = false;
+                    }
+                    n => write_all(self.master, &buffer[..n])?,
+                }
+            }
+        }
+    }
+}
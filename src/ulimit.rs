@@ -0,0 +1,201 @@
+use failure::Error;
+use nix::errno::Errno;
+use nix::libc::{
+    c_int, getrlimit, rlim_t, rlimit, setrlimit, RLIMIT_AS, RLIMIT_CORE, RLIMIT_CPU, RLIMIT_DATA,
+    RLIMIT_FSIZE, RLIMIT_LOCKS, RLIMIT_MEMLOCK, RLIMIT_MSGQUEUE, RLIMIT_NICE, RLIMIT_NOFILE,
+    RLIMIT_NPROC, RLIMIT_RSS, RLIMIT_RTPRIO, RLIMIT_RTTIME, RLIMIT_SIGPENDING, RLIMIT_STACK,
+    RLIM_INFINITY,
+};
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::str::FromStr;
+
+const UNLIMITED: &str = "unlimited";
+const RESOURCES: &[(&str, c_int)] = &[
+    ("as", RLIMIT_AS),
+    ("core", RLIMIT_CORE),
+    ("cpu", RLIMIT_CPU),
+    ("data", RLIMIT_DATA),
+    ("fsize", RLIMIT_FSIZE),
+    ("locks", RLIMIT_LOCKS),
+    ("memlock", RLIMIT_MEMLOCK),
+    ("msgqueue", RLIMIT_MSGQUEUE),
+    ("nice", RLIMIT_NICE),
+    ("nofile", RLIMIT_NOFILE),
+    ("nproc", RLIMIT_NPROC),
+    ("rss", RLIMIT_RSS),
+    ("rtprio", RLIMIT_RTPRIO),
+    ("rttime", RLIMIT_RTTIME),
+    ("sigpending", RLIMIT_SIGPENDING),
+    ("stack", RLIMIT_STACK),
+];
+
+#[derive(Debug, Fail)]
+pub(crate) enum UlimitError {
+    #[fail(display = "Invalid ulimit {}", 0)]
+    InvalidUlimit(String),
+    #[fail(display = "Unknown ulimit resource {}", 0)]
+    UnknownResource(String),
+    #[fail(display = "Soft limit of {} is higher than its hard limit", 0)]
+    SoftLimitAboveHardLimit(String),
+    #[fail(
+        display = "Hard limit of {} can't be raised to {}, the maximum allowed is {}",
+        0, 1, 2
+    )]
+    HardLimitTooHigh(String, String, String),
+}
+
+#[inline]
+fn format_limit(limit: rlim_t) -> String {
+    if limit == RLIM_INFINITY {
+        UNLIMITED.to_owned()
+    } else {
+        format!("{}", limit)
+    }
+}
+
+#[inline]
+fn parse_limit(limit: &str) -> Option<rlim_t> {
+    if limit == UNLIMITED || limit == "-1" {
+        Some(RLIM_INFINITY)
+    } else {
+        rlim_t::from_str(limit).ok()
+    }
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub(crate) struct Ulimit {
+    name: String,
+    resource: c_int,
+    soft: rlim_t,
+    hard: rlim_t,
+}
+
+impl FromStr for Ulimit {
+    type Err = UlimitError;
+
+    fn from_str(s: &str) -> Result<Ulimit, Self::Err> {
+        let invalid = || UlimitError::InvalidUlimit(s.to_owned());
+        let (name, limits) = match s.split('=').collect::<Vec<&str>>().as_slice() {
+            [name, limits] => (*name, *limits),
+            _ => Err(invalid())?,
+        };
+        let resource = RESOURCES
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, r)| *r)
+            .ok_or_else(|| UlimitError::UnknownResource(name.to_owned()))?;
+        let (soft, hard) = match limits.split(':').collect::<Vec<&str>>().as_slice() {
+            [soft] => {
+                let soft = parse_limit(soft).ok_or_else(invalid)?;
+                (soft, soft)
+            }
+            [soft, hard] => (
+                parse_limit(soft).ok_or_else(invalid)?,
+                parse_limit(hard).ok_or_else(invalid)?,
+            ),
+            _ => Err(invalid())?,
+        };
+        if soft > hard {
+            Err(UlimitError::SoftLimitAboveHardLimit(name.to_owned()))?
+        }
+        Ok(Ulimit {
+            name: name.to_owned(),
+            resource,
+            soft,
+            hard,
+        })
+    }
+}
+
+impl Ulimit {
+    // Fails when the hard limit is above the one ruthless has, as it can't be raised without
+    // CAP_SYS_RESOURCE on the host. The payload inherits the limits of ruthless, so this runs
+    // before spawning it.
+    pub(crate) fn check(&self) -> Result<(), Error> {
+        let mut current = rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        Errno::result(unsafe { getrlimit(self.resource, &mut current) })?;
+        if self.hard > current.rlim_max {
+            Err(UlimitError::HardLimitTooHigh(
+                self.name.clone(),
+                format_limit(self.hard),
+                format_limit(current.rlim_max),
+            ))?
+        }
+        Ok(())
+    }
+
+    // Runs between fork and exec of the payload, so that the limits don't apply to ruthless.
+    pub(crate) fn apply(&self) -> io::Result<()> {
+        let limit = rlimit {
+            rlim_cur: self.soft,
+            rlim_max: self.hard,
+        };
+        if unsafe { setrlimit(self.resource, &limit) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(ulimit: &str) -> (c_int, rlim_t, rlim_t) {
+        let ulimit = Ulimit::from_str(ulimit).unwrap();
+        (ulimit.resource, ulimit.soft, ulimit.hard)
+    }
+
+    #[test]
+    fn parses_ulimits() {
+        assert_eq!(limits("nofile=1024"), (RLIMIT_NOFILE as c_int, 1024, 1024));
+        assert_eq!(
+            limits("nofile=1024:2048"),
+            (RLIMIT_NOFILE as c_int, 1024, 2048)
+        );
+        assert_eq!(
+            limits("core=0:unlimited"),
+            (RLIMIT_CORE as c_int, 0, RLIM_INFINITY)
+        );
+        assert_eq!(
+            limits("stack=-1"),
+            (RLIMIT_STACK as c_int, RLIM_INFINITY, RLIM_INFINITY)
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_ulimits() {
+        for ulimit in [
+            "nofile",
+            "nofile=",
+            "nofile=a",
+            "nofile=1:2:3",
+            "nofile=1=2",
+            "nofile=-2",
+        ]
+        .iter()
+        {
+            assert!(
+                matches!(Ulimit::from_str(ulimit), Err(UlimitError::InvalidUlimit(_))),
+                "{}",
+                ulimit
+            );
+        }
+        assert!(matches!(
+            Ulimit::from_str("files=1"),
+            Err(UlimitError::UnknownResource(_))
+        ));
+        assert!(matches!(
+            Ulimit::from_str("nofile=2048:1024"),
+            Err(UlimitError::SoftLimitAboveHardLimit(_))
+        ));
+        assert!(matches!(
+            Ulimit::from_str("nofile=unlimited:1024"),
+            Err(UlimitError::SoftLimitAboveHardLimit(_))
+        ));
+    }
+}
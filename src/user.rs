@@ -0,0 +1,167 @@
+use crate::idmap::IdMap;
+use failure::Error;
+use nix::libc::{c_ulong, prctl, PR_SET_KEEPCAPS};
+use nix::unistd::{setgid, setgroups, setuid, Gid, Uid};
+use std::fs::read_to_string;
+use std::io;
+use std::str::FromStr;
+
+const PASSWD_FILE: &str = "/etc/passwd";
+const GROUP_FILE: &str = "/etc/group";
+const DEFAULT_HOME: &str = "/";
+
+#[derive(Debug, Fail)]
+pub(crate) enum UserError {
+    #[fail(display = "Unknown user {}", 0)]
+    UnknownUser(String),
+    #[fail(display = "Unknown group {}", 0)]
+    UnknownGroup(String),
+}
+
+struct PasswdEntry {
+    name: String,
+    uid: u32,
+    gid: u32,
+    home: String,
+}
+
+struct GroupEntry {
+    name: String,
+    gid: u32,
+    members: Vec<String>,
+}
+
+fn read_database(path: &str) -> Vec<Vec<String>> {
+    read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .filter(|l| !l.trim().is_empty() && !l.starts_with('#'))
+        .map(|l| l.split(':').map(|f| f.to_owned()).collect())
+        .collect()
+}
+
+fn read_passwd() -> Vec<PasswdEntry> {
+    read_database(PASSWD_FILE)
+        .into_iter()
+        .filter_map(|fields| match fields.as_slice() {
+            [name, _, uid, gid, _, home, ..] => Some(PasswdEntry {
+                name: name.to_owned(),
+                uid: u32::from_str(uid).ok()?,
+                gid: u32::from_str(gid).ok()?,
+                home: home.to_owned(),
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+fn read_groups() -> Vec<GroupEntry> {
+    read_database(GROUP_FILE)
+        .into_iter()
+        .filter_map(|fields| match fields.as_slice() {
+            [name, _, gid, members, ..] => Some(GroupEntry {
+                name: name.to_owned(),
+                gid: u32::from_str(gid).ok()?,
+                members: members
+                    .split(',')
+                    .filter(|m| !m.is_empty())
+                    .map(|m| m.to_owned())
+                    .collect(),
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+// [groups] is none when the supplementary groups can't be changed.
+#[derive(Clone)]
+pub(crate) struct User {
+    uid: u32,
+    gid: u32,
+    groups: Option<Vec<Gid>>,
+    pub(crate) home: String,
+}
+
+impl User {
+    pub(crate) fn resolve(spec: &str) -> Result<User, Error> {
+        let mut parts = spec.splitn(2, ':');
+        let user = parts.next().unwrap_or("");
+        let group = parts.next();
+        let passwd = read_passwd();
+        let entry = if user.is_empty() {
+            passwd.iter().find(|e| e.uid == 0)
+        } else if let Ok(uid) = u32::from_str(user) {
+            passwd.iter().find(|e| e.uid == uid)
+        } else {
+            Some(
+                passwd
+                    .iter()
+                    .find(|e| e.name == user)
+                    .ok_or_else(|| UserError::UnknownUser(user.to_owned()))?,
+            )
+        };
+        let uid = match u32::from_str(user) {
+            Ok(uid) => uid,
+            Err(_) => entry.map(|e| e.uid).unwrap_or(0),
+        };
+        let groups = read_groups();
+        let gid = match group {
+            Some(g) => match u32::from_str(g) {
+                Ok(gid) => gid,
+                Err(_) => groups
+                    .iter()
+                    .find(|e| e.name == g)
+                    .map(|e| e.gid)
+                    .ok_or_else(|| UserError::UnknownGroup(g.to_owned()))?,
+            },
+            None => entry.map(|e| e.gid).unwrap_or(0),
+        };
+        let mut all_groups = vec![Gid::from_raw(gid)];
+        if let (Some(e), None) = (entry, group) {
+            all_groups.extend(
+                groups
+                    .iter()
+                    .filter(|g| g.gid != gid && g.members.contains(&e.name))
+                    .map(|g| Gid::from_raw(g.gid)),
+            );
+        }
+        Ok(User {
+            uid,
+            gid,
+            groups: Some(all_groups),
+            home: entry
+                .map(|e| e.home.clone())
+                .unwrap_or_else(|| DEFAULT_HOME.to_owned()),
+        })
+    }
+
+    // setgroups is denied when only root is mapped, and groups out of the gid map don't exist in
+    // the container.
+    pub(crate) fn restrict_to(&mut self, id_map: IdMap) {
+        if !id_map.allows_setgroups() {
+            self.groups = None;
+        } else if let Some(groups) = self.groups.as_mut() {
+            groups.retain(|g| id_map.maps_gid(g.as_raw()));
+        }
+    }
+
+    // Keeps the permitted capabilities through setuid, so that they can be set afterwards.
+    pub(crate) fn switch(&self) -> io::Result<()> {
+        if let Some(groups) = self.groups.as_ref() {
+            setgroups(groups).map_err(to_io_error)?;
+        }
+        setgid(Gid::from_raw(self.gid)).map_err(to_io_error)?;
+        if unsafe { prctl(PR_SET_KEEPCAPS, 1 as c_ulong, 0, 0, 0) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        setuid(Uid::from_raw(self.uid)).map_err(to_io_error)
+    }
+}
+
+#[inline]
+fn to_io_error(error: nix::Error) -> io::Error {
+    match error {
+        nix::Error::Sys(errno) => io::Error::from_raw_os_error(errno as i32),
+        e => io::Error::other(e.to_string()),
+    }
+}